@@ -0,0 +1,116 @@
+/*
+Copyright 2020 <盏一 w@hidva.com>
+Licensed under the Apache License, Version 2.0 (the "License");
+you may not use this file except in compliance with the License.
+You may obtain a copy of the License at
+
+http://www.apache.org/licenses/LICENSE-2.0
+
+Unless required by applicable law or agreed to in writing, software
+distributed under the License is distributed on an "AS IS" BASIS,
+WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+See the License for the specific language governing permissions and
+limitations under the License.
+*/
+use crate::gpdbcli::*;
+use crate::lock::SessionId;
+use postgres::{SimpleQueryMessage, SimpleQueryRow};
+use std::collections::HashMap;
+
+// What the coordinator backend of a session is doing, from pg_stat_activity.
+#[derive(Default, Clone)]
+pub struct Activity {
+    pub pid: Option<i64>,
+    pub usename: Option<String>,
+    pub state: Option<String>,
+    pub query: Option<String>,
+    // in seconds.
+    pub xact_age: Option<i64>,
+    pub query_age: Option<i64>,
+}
+
+// The backends on the segments, (gp_segment_id, pid) -> (session, activity).
+pub type SegmentBackends = HashMap<(i64, i64), (SessionId, Activity)>;
+
+#[derive(Default)]
+pub struct ActivityInfo {
+    pub sessions: HashMap<SessionId, Activity>,
+}
+
+impl ActivityInfo {
+    fn getactivity(row: &SimpleQueryRow) -> (SessionId, Activity) {
+        let mut activity = Activity::default();
+
+        macro_rules! fill {
+            ($field: ident) => {
+                activity.$field = row.get(stringify!($field)).map(|v| v.parse().unwrap())
+            };
+        }
+
+        fill!(pid);
+        fill!(usename);
+        fill!(state);
+        fill!(query);
+        fill!(xact_age);
+        fill!(query_age);
+
+        (row.get("sess_id").unwrap().parse().unwrap(), activity)
+    }
+
+    fn process(qres: Vec<SimpleQueryMessage>) -> ActivityInfo {
+        let mut info = ActivityInfo::default();
+        for rowres in qres.iter() {
+            if let SimpleQueryMessage::Row(row) = rowres {
+                let (sessid, activity) = ActivityInfo::getactivity(row);
+                // a session may have several backends on the coordinator, prefer the one running a query.
+                if activity.query.is_some() || !info.sessions.contains_key(&sessid) {
+                    info.sessions.insert(sessid, activity);
+                }
+            }
+        }
+        info
+    }
+
+    fn columns(caps: &Capabilities) -> &'static str {
+        if !caps.activity_pid {
+            "sess_id,procpid as pid,usename,null as state,current_query as query,\
+            extract(epoch from now() - xact_start)::bigint as xact_age,\
+            extract(epoch from now() - query_start)::bigint as query_age"
+        } else if !caps.gp_segment_id {
+            "pid as sess_id,pid,usename,state,query,\
+            extract(epoch from now() - xact_start)::bigint as xact_age,\
+            extract(epoch from now() - query_start)::bigint as query_age"
+        } else {
+            "sess_id,pid,usename,state,query,\
+            extract(epoch from now() - xact_start)::bigint as xact_age,\
+            extract(epoch from now() - query_start)::bigint as query_age"
+        }
+    }
+
+    pub fn get(cli: &mut GPDBCli) -> Result<ActivityInfo, GPDBError> {
+        let qstr = format!(
+            "select {} from pg_stat_activity",
+            ActivityInfo::columns(&cli.ver.caps)
+        );
+        Ok(ActivityInfo::process(cli.try_query(qstr.as_str())?))
+    }
+
+    // The backends on the primary segments, Greenplum only.
+    pub fn get_segments(cli: &mut GPDBCli) -> Result<SegmentBackends, GPDBError> {
+        let qstr = format!(
+            "select gp_segment_id,{} from gp_dist_random('pg_stat_activity')",
+            ActivityInfo::columns(&cli.ver.caps)
+        );
+        let mut backends = HashMap::new();
+        for rowres in cli.try_query(qstr.as_str())?.iter() {
+            if let SimpleQueryMessage::Row(row) = rowres {
+                let segid: i64 = row.get("gp_segment_id").unwrap().parse().unwrap();
+                let (sessid, activity) = ActivityInfo::getactivity(row);
+                if let Some(pid) = activity.pid {
+                    backends.insert((segid, pid), (sessid, activity));
+                }
+            }
+        }
+        Ok(backends)
+    }
+}
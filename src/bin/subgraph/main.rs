@@ -19,7 +19,7 @@ fn add_graph(graph: &mut HashMap<SessionId, Vec<SessionId>>, line: &str) -> anyh
     } else {
         graph.insert(left, vec![right]);
     }
-    return Ok(());
+    Ok(())
 }
 
 fn main() {
@@ -55,6 +55,5 @@ fn main() {
         }
     }
 
-    println!("{}", dot::render_tiny(&graph, meet.iter().map(|v| *v)));
-    return;
+    println!("{}", dot::render_tiny(&graph, meet.iter().copied()));
 }
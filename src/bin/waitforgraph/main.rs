@@ -12,17 +12,330 @@ WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
 See the License for the specific language governing permissions and
 limitations under the License.
 */
+use std::collections::HashSet;
+use std::time::Duration;
+use waitforgraph::activity::*;
+use waitforgraph::cancel::*;
 use waitforgraph::gpdbcli::*;
 use waitforgraph::graph::*;
 use waitforgraph::lock::*;
+use waitforgraph::orphan::*;
+use waitforgraph::rwgraph::*;
+
+const FORMATS: &[&str] = &[
+    "dot",
+    "dot-segments",
+    "mermaid",
+    "plantuml",
+    "html",
+    "svg",
+    "ascii",
+    "json",
+];
+// where the edges come from: the conflicts of the locks in pg_locks, or gp_dist_wait_status().
+const SOURCES: &[&str] = &["locks", "dist-wait"];
+
+mod tui;
+
+struct Args {
+    format: String,
+    connstr: String,
+    tui: bool,
+    // refresh interval of tui, in seconds.
+    interval: u64,
+    // collect pg_locks from every segment in utility mode.
+    utility: bool,
+    segment_timeout: Option<Duration>,
+    timeouts: Timeouts,
+    source: String,
+    // report the edges only one of the sources has instead of drawing the graph.
+    cross_check: bool,
+    // compare the edges with pg_blocking_pids() instead of drawing the graph.
+    verify: bool,
+    // print the rw-dependencies between serializable transactions instead.
+    siread: bool,
+    // list the sessions holding locks without a backend on the coordinator instead.
+    orphans: bool,
+    // fetch the contents of the rows sessions wait for.
+    rows: bool,
+    // merge the locks on partitions, indexes, ... into the lock on their table.
+    rollup: bool,
+    // print the sessions to cancel instead.
+    cancel_plan: bool,
+    // the waiters the cancel plan must unblock right away.
+    unblock: Vec<SessionId>,
+    cancel_weight: CancelWeight,
+    // how lock modes unknown to us conflict.
+    conflict_policy: ConflictPolicy,
+}
+
+fn timeout_arg<'a>(arg: &str, timeouts: &'a mut Timeouts) -> Option<&'a mut Option<Duration>> {
+    match arg {
+        "--connect-timeout" => Some(&mut timeouts.connect),
+        "--statement-timeout" => Some(&mut timeouts.statement),
+        "--lock-timeout" => Some(&mut timeouts.lock),
+        _ => None,
+    }
+}
+
+// waitforgraph [--format FORMAT] [--utility [--segment-timeout SECONDS]] [OPTIONS] [CONNSTR]
+// waitforgraph --siread [--utility [--segment-timeout SECONDS]] [OPTIONS] [CONNSTR], always DOT
+// waitforgraph tui [--interval SECONDS] [OPTIONS] [CONNSTR]
+// OPTIONS: [--connect-timeout SECONDS] [--statement-timeout SECONDS] [--lock-timeout SECONDS],
+// 0 disables the timeout, so does --segment-timeout 0. [--unknown-mode-conflict always|never|same]
+// [--source locks|dist-wait] picks where the edges come from, [--cross-check] compares the two,
+// [--verify] compares the edges derived from pg_locks with pg_blocking_pids(), [--orphans] lists
+// the sessions holding locks on segments after their coordinator backend is gone, [--rows] fetches
+// the rows waited for by their ctid, [--rollup] merges the locks on partitions, indexes, TOAST and
+// append-optimized auxiliary relations into the lock on their top-level table.
+// [--cancel-plan [--unblock SESSID,...] [--cancel-weight count|xact-age|query-age]] lists the
+// sessions to cancel to break every cycle and unblock the given waiters.
+fn parse_args() -> Args {
+    let mut args = Args {
+        format: String::from("dot"),
+        connstr: String::new(),
+        tui: false,
+        interval: 2,
+        utility: false,
+        source: String::from("locks"),
+        cross_check: false,
+        verify: false,
+        siread: false,
+        orphans: false,
+        rows: false,
+        rollup: false,
+        cancel_plan: false,
+        unblock: vec![],
+        cancel_weight: CancelWeight::default(),
+        segment_timeout: Some(Duration::from_secs(10)),
+        timeouts: Timeouts {
+            connect: Some(Duration::from_secs(10)),
+            connect_set: false,
+            statement: Some(Duration::from_secs(60)),
+            lock: Some(Duration::from_secs(10)),
+        },
+        conflict_policy: ConflictPolicy::default(),
+    };
+    let mut argv = std::env::args().skip(1).peekable();
+    if argv.peek().map(String::as_str) == Some("tui") {
+        args.tui = true;
+        argv.next();
+    }
+    while let Some(arg) = argv.next() {
+        if arg == "--interval" {
+            let interval = argv.next().expect("--interval requires a value");
+            args.interval = interval.parse().expect("invalid --interval");
+        } else if arg == "--utility" {
+            args.utility = true;
+        } else if arg == "--source" {
+            args.source = argv.next().expect("--source requires a value");
+        } else if arg == "--cross-check" {
+            args.cross_check = true;
+        } else if arg == "--verify" {
+            args.verify = true;
+        } else if arg == "--siread" {
+            args.siread = true;
+        } else if arg == "--orphans" {
+            args.orphans = true;
+        } else if arg == "--rows" {
+            args.rows = true;
+        } else if arg == "--rollup" {
+            args.rollup = true;
+        } else if arg == "--cancel-plan" {
+            args.cancel_plan = true;
+        } else if arg == "--unblock" {
+            let sessions = argv.next().expect("--unblock requires a value");
+            for sessid in sessions.split(',') {
+                args.unblock
+                    .push(sessid.trim().parse().expect("invalid --unblock"));
+            }
+        } else if arg == "--cancel-weight" {
+            let weight = argv.next().expect("--cancel-weight requires a value");
+            args.cancel_weight = weight.parse().unwrap_or_else(|e| panic!("{}", e));
+        } else if arg == "--segment-timeout" {
+            let timeout = argv.next().expect("--segment-timeout requires a value");
+            let secs: u64 = timeout.parse().expect("invalid --segment-timeout");
+            args.segment_timeout = Some(Duration::from_secs(secs)).filter(|d| !d.is_zero());
+        } else if let Some(timeout) = timeout_arg(&arg, &mut args.timeouts) {
+            let value = argv
+                .next()
+                .unwrap_or_else(|| panic!("{} requires a value", arg));
+            let secs: u64 = value.parse().unwrap_or_else(|_| panic!("invalid {}", arg));
+            *timeout = Some(Duration::from_secs(secs)).filter(|d| !d.is_zero());
+            if arg == "--connect-timeout" {
+                args.timeouts.connect_set = true;
+            }
+        } else if arg == "--unknown-mode-conflict" {
+            let policy = argv
+                .next()
+                .expect("--unknown-mode-conflict requires a value");
+            args.conflict_policy = policy.parse().unwrap_or_else(|e| panic!("{}", e));
+        } else if arg == "--format" {
+            args.format = argv.next().expect("--format requires a value");
+        } else if let Some(format) = arg.strip_prefix("--format=") {
+            args.format = String::from(format);
+        } else {
+            args.connstr = arg;
+        }
+    }
+    if !FORMATS.contains(&args.format.as_str()) {
+        panic!(
+            "UnknownFormat={}; expected one of {:?}",
+            args.format, FORMATS
+        );
+    }
+    if !SOURCES.contains(&args.source.as_str()) {
+        panic!(
+            "UnknownSource={}; expected one of {:?}",
+            args.source, SOURCES
+        );
+    }
+    args
+}
+
+fn print_diff(ours: &[(SessionId, SessionId)], theirs: &[(SessionId, SessionId)], source: &str) {
+    println!("edges only in pg_locks: {}", ours.len());
+    for (waiter, holder) in ours {
+        println!("{} -> {}", waiter, holder);
+    }
+    println!("edges only in {}: {}", source, theirs.len());
+    for (waiter, holder) in theirs {
+        println!("{} -> {}", waiter, holder);
+    }
+}
+
+// The slots of resource queues and resource groups are not locks gp_dist_wait_status() knows.
+fn cross_check(wfg: &WFGraph, waits: &[DistWait]) {
+    let pairs = waits
+        .iter()
+        .map(|wait| (wait.waiter, wait.holder))
+        .collect();
+    let (only_locks, only_dist) = wfg.cross_check(&pairs, |obj| !obj.is_slot());
+    print_diff(&only_locks, &only_dist, "gp_dist_wait_status()");
+}
+
+// pg_blocking_pids() on the coordinator knows nothing about the locks on segments.
+fn verify(wfg: &WFGraph, pairs: &HashSet<(SessionId, SessionId)>) {
+    let (extra, missing) = wfg.cross_check(pairs, LockObj::on_coordinator);
+    print_diff(&extra, &missing, "pg_blocking_pids()");
+    if !missing.is_empty() {
+        println!(
+            "pg_blocking_pids() also reports the waiters queued ahead, \
+            which are not edges of the wait-for graph"
+        );
+    }
+}
+
+// Timeouts exit with status 2, any other error with 1.
+fn or_exit<T>(res: Result<T, GPDBError>) -> T {
+    res.unwrap_or_else(|err| {
+        eprintln!("{}", err);
+        std::process::exit(if err.is_timeout() { 2 } else { 1 });
+    })
+}
+
+// pg_stat_activity is read after pg_locks, so sessions ending in between look like orphans too.
+fn orphans(cli: &mut GPDBCli, wfg: WFGraph) {
+    let activity = or_exit(ActivityInfo::get(cli));
+    let backends = ActivityInfo::get_segments(cli).unwrap_or_else(|err| {
+        eprintln!("cannot read pg_stat_activity of the segments: {}", err);
+        Default::default()
+    });
+    println!(
+        "{}",
+        Orphans::new(&wfg, &activity, &backends, &cli.ver.caps).render()
+    );
+}
 
 fn main() {
-    let argv: Vec<String> = std::env::args().collect();
-    let mut cli = GPDBCli::new(if argv.len() >= 2 {
-        argv[1].as_str()
+    let args = parse_args();
+    let mut cli = or_exit(GPDBCli::connect(args.connstr.as_str(), &args.timeouts));
+    if args.tui {
+        tui::run(
+            cli,
+            Duration::from_secs(args.interval),
+            args.conflict_policy,
+        )
+        .unwrap();
+        return;
+    }
+    let mut lockinfo = if args.utility {
+        let (lockinfo, failed) = or_exit(LockInfo::get_utility(&mut cli, args.segment_timeout));
+        for segerr in failed.iter() {
+            eprintln!("{}", segerr);
+        }
+        lockinfo
     } else {
-        ""
-    });
-    let wfg = WFGraph::new(LockInfo::get(&mut cli));
-    println!("{}", dot::render(&wfg));
+        or_exit(LockInfo::get(&mut cli))
+    };
+    if args.siread {
+        let activity = or_exit(ActivityInfo::get(&mut cli));
+        println!("{}", RWGraph::new(&lockinfo, activity).render());
+        return;
+    }
+    lockinfo.conflict_policy = args.conflict_policy;
+    if args.rows {
+        lockinfo.fetch_rows(&mut cli);
+    }
+    if args.rollup {
+        or_exit(lockinfo.fetch_rollup(&mut cli));
+    }
+    if args.orphans {
+        if !cli.ver.caps.gp_segment_id {
+            eprintln!("orphan sessions need Greenplum, got {}", cli.ver);
+            std::process::exit(1);
+        }
+        orphans(&mut cli, WFGraph::new(lockinfo));
+        return;
+    }
+    let waits = if args.source == "dist-wait" || args.cross_check {
+        if !cli.ver.caps.gdd {
+            eprintln!(
+                "gp_dist_wait_status() needs Greenplum 6 or later, got {}",
+                cli.ver
+            );
+            std::process::exit(1);
+        }
+        or_exit(LockInfo::get_dist_wait(&mut cli))
+    } else {
+        vec![]
+    };
+    if args.verify {
+        if !cli.ver.caps.blocking_pids {
+            eprintln!(
+                "pg_blocking_pids() needs Greenplum 7 or later, got {}",
+                cli.ver
+            );
+            std::process::exit(1);
+        }
+        let pairs = or_exit(LockInfo::get_blocking_pids(&mut cli));
+        verify(&WFGraph::new(lockinfo), &pairs);
+        return;
+    }
+    if args.cross_check {
+        cross_check(&WFGraph::new(lockinfo), &waits);
+        return;
+    }
+    let wfg = if args.source == "dist-wait" {
+        WFGraph::new_dist_wait(lockinfo, &waits)
+    } else {
+        WFGraph::new(lockinfo)
+    };
+    if args.cancel_plan {
+        let activity = or_exit(ActivityInfo::get(&mut cli));
+        let plan = CancelPlan::new(&wfg, activity, args.unblock, args.cancel_weight);
+        println!("{}", plan.render());
+        return;
+    }
+    let output = match args.format.as_str() {
+        "mermaid" => mermaid::render(&wfg),
+        "plantuml" => plantuml::render(&wfg),
+        "html" => html::render(&wfg, &or_exit(ActivityInfo::get(&mut cli))),
+        "svg" => svg::render(&wfg),
+        "ascii" => ascii::render(&wfg),
+        "json" => json::render(&wfg),
+        "dot-segments" => dot::render_segments(&wfg),
+        _ => dot::render(&wfg),
+    };
+    println!("{}", output);
 }
@@ -0,0 +1,480 @@
+/*
+Copyright 2020 <盏一 w@hidva.com>
+Licensed under the Apache License, Version 2.0 (the "License");
+you may not use this file except in compliance with the License.
+You may obtain a copy of the License at
+
+http://www.apache.org/licenses/LICENSE-2.0
+
+Unless required by applicable law or agreed to in writing, software
+distributed under the License is distributed on an "AS IS" BASIS,
+WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+See the License for the specific language governing permissions and
+limitations under the License.
+*/
+// `waitforgraph tui`, something like top for locks.
+use crossterm::cursor::{Hide, MoveTo, Show};
+use crossterm::event::{self, Event, KeyCode, KeyEventKind, KeyModifiers};
+use crossterm::style::{Attribute, Color, Print, ResetColor, SetAttribute, SetForegroundColor};
+use crossterm::terminal::{self, EnterAlternateScreen, LeaveAlternateScreen};
+use crossterm::{execute, queue};
+use std::collections::HashSet;
+use std::io::{self, Write};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::thread;
+use std::time::{Duration, Instant};
+use waitforgraph::activity::*;
+use waitforgraph::fmt_age;
+use waitforgraph::gpdbcli::*;
+use waitforgraph::graph::*;
+use waitforgraph::lock::*;
+
+const HELP: &str = "j/k move  space/left/right collapse  r root blocker  R refresh  q quit";
+
+// Restore the terminal even if we panic.
+struct Terminal;
+
+impl Terminal {
+    fn new() -> io::Result<Terminal> {
+        terminal::enable_raw_mode()?;
+        execute!(io::stdout(), EnterAlternateScreen, Hide)?;
+        Ok(Terminal)
+    }
+}
+
+impl Drop for Terminal {
+    fn drop(&mut self) {
+        let _ = execute!(io::stdout(), Show, LeaveAlternateScreen);
+        let _ = terminal::disable_raw_mode();
+    }
+}
+
+struct Row {
+    sessid: SessionId,
+    depth: usize,
+    has_children: bool,
+    // sessid is one of its own ancestors, i.e. we walked around a cycle.
+    cyclic: bool,
+    // sessid has a row above already, where its subtree is.
+    repeated: bool,
+}
+
+type Fetched = Result<(LockInfo, ActivityInfo), GPDBError>;
+
+// Reads the locks over `cli` whenever asked to, so that a hung query does not freeze the screen.
+fn fetcher(mut cli: GPDBCli, requests: Receiver<()>, results: Sender<Fetched>) {
+    while requests.recv().is_ok() {
+        let fetched = LockInfo::get(&mut cli)
+            .and_then(|lockinfo| Ok((lockinfo, ActivityInfo::get(&mut cli)?)));
+        if results.send(fetched).is_err() {
+            return;
+        }
+    }
+}
+
+struct App {
+    requests: Sender<()>,
+    results: Receiver<Fetched>,
+    // a refresh was requested and has not come back yet.
+    refreshing: bool,
+    conflict_policy: ConflictPolicy,
+    wfg: WFGraph,
+    activity: ActivityInfo,
+    in_cycle: HashSet<SessionId>,
+    collapsed: HashSet<SessionId>,
+    rows: Vec<Row>,
+    selected: usize,
+    offset: usize,
+    refreshed: Instant,
+    // why the last refresh failed, the graph is the one read before then.
+    error: Option<GPDBError>,
+}
+
+fn fit(s: &str, width: usize) -> String {
+    let ret: String = s.chars().take(width).collect();
+    format!("{:<width$}", ret, width = width)
+}
+
+fn wrap(s: &str, width: usize, out: &mut Vec<String>) {
+    let width = width.max(1);
+    for line in s.lines() {
+        let chars: Vec<char> = line.chars().collect();
+        if chars.is_empty() {
+            out.push(String::new());
+        }
+        for chunk in chars.chunks(width) {
+            out.push(chunk.iter().collect());
+        }
+    }
+}
+
+fn fmt_sessions(sessions: &[SessionId]) -> String {
+    let v: Vec<String> = sessions.iter().map(|s| s.to_string()).collect();
+    v.join(", ")
+}
+
+impl App {
+    fn new(cli: GPDBCli, conflict_policy: ConflictPolicy) -> App {
+        let (requests, rx) = mpsc::channel();
+        let (tx, results) = mpsc::channel();
+        thread::spawn(move || fetcher(cli, rx, tx));
+        let mut app = App {
+            requests,
+            results,
+            refreshing: false,
+            conflict_policy,
+            wfg: WFGraph::default(),
+            activity: ActivityInfo::default(),
+            in_cycle: HashSet::new(),
+            collapsed: HashSet::new(),
+            rows: vec![],
+            selected: 0,
+            offset: 0,
+            refreshed: Instant::now(),
+            error: None,
+        };
+        app.refresh();
+        app
+    }
+
+    fn selected_sessid(&self) -> Option<SessionId> {
+        self.rows.get(self.selected).map(|row| row.sessid)
+    }
+
+    // Ask the fetcher for new locks, unless it is still busy with the last request.
+    fn refresh(&mut self) {
+        if !self.refreshing {
+            self.refreshing = self.requests.send(()).is_ok();
+        }
+    }
+
+    // Take the locks the fetcher came back with, if it did.
+    fn poll_refresh(&mut self) {
+        let fetched = match self.results.try_recv() {
+            Ok(fetched) => fetched,
+            Err(_) => return,
+        };
+        self.refreshing = false;
+        let (mut lockinfo, activity) = match fetched {
+            Ok(fetched) => fetched,
+            Err(err) => {
+                self.error = Some(err);
+                return;
+            }
+        };
+        self.error = None;
+        let selected = self.selected_sessid();
+        lockinfo.conflict_policy = self.conflict_policy;
+        self.wfg = WFGraph::new(lockinfo);
+        self.activity = activity;
+        self.in_cycle = self.wfg.cycles().into_iter().flatten().collect();
+        self.refreshed = Instant::now();
+        self.build_rows();
+        if let Some(sessid) = selected {
+            self.select(sessid);
+        }
+    }
+
+    // A session blocking several others of the same tree is expanded only the first time, or
+    // the rows would grow exponentially with the pile-ups.
+    fn add_rows(
+        &mut self,
+        sessid: SessionId,
+        depth: usize,
+        path: &mut Vec<SessionId>,
+        visited: &mut HashSet<SessionId>,
+    ) {
+        let cyclic = path.contains(&sessid);
+        let repeated = !cyclic && !visited.insert(sessid);
+        let children = self.wfg.blocking(sessid);
+        self.rows.push(Row {
+            sessid,
+            depth,
+            has_children: !children.is_empty(),
+            cyclic,
+            repeated,
+        });
+        if cyclic || repeated || self.collapsed.contains(&sessid) {
+            return;
+        }
+        path.push(sessid);
+        for child in children {
+            self.add_rows(child, depth + 1, path, visited);
+        }
+        path.pop();
+    }
+
+    fn build_rows(&mut self) {
+        self.rows.clear();
+        let mut path = vec![];
+        let mut visited = HashSet::new();
+        for root in self.wfg.tree_roots() {
+            self.add_rows(root, 0, &mut path, &mut visited);
+        }
+        self.selected = self.selected.min(self.rows.len().saturating_sub(1));
+    }
+
+    fn select(&mut self, sessid: SessionId) {
+        if let Some(idx) = self.rows.iter().position(|row| row.sessid == sessid) {
+            self.selected = idx;
+        }
+    }
+
+    fn collapse(&mut self, collapsed: Option<bool>) {
+        if let Some(sessid) = self.selected_sessid() {
+            // None means toggle.
+            let collapsed = collapsed.unwrap_or(!self.collapsed.contains(&sessid));
+            if collapsed {
+                self.collapsed.insert(sessid);
+            } else {
+                self.collapsed.remove(&sessid);
+            }
+            self.build_rows();
+            self.select(sessid);
+        }
+    }
+
+    // Follow the first blocker until a session that waits for nobody, or until we go round a cycle.
+    fn jump_to_root(&mut self) {
+        if let Some(mut sessid) = self.selected_sessid() {
+            let mut seen = HashSet::new();
+            while seen.insert(sessid) {
+                match self.wfg.blockers(sessid).first() {
+                    Some(&blocker) => sessid = blocker,
+                    None => break,
+                }
+            }
+            if let Some(idx) = self
+                .rows
+                .iter()
+                .position(|row| row.sessid == sessid && row.depth == 0)
+            {
+                self.selected = idx;
+            } else {
+                self.select(sessid);
+            }
+        }
+    }
+
+    fn tree_line(&self, row: &Row) -> String {
+        let marker = if row.cyclic {
+            "↻ "
+        } else if !row.has_children || row.repeated {
+            "  "
+        } else if self.collapsed.contains(&row.sessid) {
+            "▸ "
+        } else {
+            "▾ "
+        };
+        let query = if row.repeated {
+            "(see above)"
+        } else {
+            self.activity
+                .sessions
+                .get(&row.sessid)
+                .and_then(|a| a.query.as_deref())
+                .unwrap_or("")
+        };
+        format!(
+            "{}{}{} {}",
+            "  ".repeat(row.depth),
+            marker,
+            self.wfg.node_label(row.sessid),
+            query.split_whitespace().collect::<Vec<&str>>().join(" ")
+        )
+    }
+
+    fn details(&self, width: usize) -> Vec<String> {
+        let mut lines = vec![];
+        let sessid = match self.selected_sessid() {
+            Some(sessid) => sessid,
+            None => {
+                lines.push(String::from("no session is waiting for a lock."));
+                return lines;
+            }
+        };
+        let activity = self
+            .activity
+            .sessions
+            .get(&sessid)
+            .cloned()
+            .unwrap_or_default();
+        if let Some(prepared) = self.wfg.prepared(sessid) {
+            lines.push(prepared.to_string());
+        }
+        lines.push(format!(
+            "session {}  pid {}  user {}  state {}",
+            sessid,
+            activity
+                .pid
+                .map(|p| p.to_string())
+                .unwrap_or_else(|| String::from("-")),
+            activity.usename.as_deref().unwrap_or("-"),
+            activity.state.as_deref().unwrap_or("-")
+        ));
+        lines.push(format!(
+            "xact age {}  query age {}",
+            fmt_age(activity.xact_age),
+            fmt_age(activity.query_age)
+        ));
+        let upgrades = self.wfg.upgrade_cycles();
+        for cycle in self.wfg.cycles() {
+            if cycle.contains(&sessid) {
+                let upgrade = if upgrades.contains(&cycle) {
+                    " (lock upgrade)"
+                } else {
+                    ""
+                };
+                lines.push(format!("in cycle: {}{}", fmt_sessions(&cycle), upgrade));
+            }
+        }
+        lines.push(String::new());
+        wrap(activity.query.as_deref().unwrap_or("-"), width, &mut lines);
+        lines.push(String::new());
+        lines.push(format!(
+            "blocked by: {}",
+            fmt_sessions(&self.wfg.blockers(sessid))
+        ));
+        lines.push(format!(
+            "blocking: {}",
+            fmt_sessions(&self.wfg.blocking(sessid))
+        ));
+        let waiting = self.wfg.desc_waiting(sessid);
+        lines.push(format!("waiting for ({}):", waiting.len()));
+        for lock in waiting.iter() {
+            wrap(format!("  {}", lock).as_str(), width, &mut lines);
+        }
+        let unexplained = self.wfg.desc_unexplained(sessid);
+        if !unexplained.is_empty() {
+            lines.push(format!("no visible blocker ({}):", unexplained.len()));
+            for wait in unexplained.iter() {
+                wrap(format!("  {}", wait).as_str(), width, &mut lines);
+            }
+        }
+        let held = self.wfg.desc_held(sessid);
+        lines.push(format!("holding ({}):", held.len()));
+        for lock in held.iter() {
+            wrap(format!("  {}", lock).as_str(), width, &mut lines);
+        }
+        lines
+    }
+
+    fn draw(&mut self, out: &mut impl Write) -> io::Result<()> {
+        let (width, height) = terminal::size()?;
+        let (width, height) = (width as usize, height as usize);
+        let left = width * 2 / 5;
+        let right = width.saturating_sub(left + 2);
+        let body = height.saturating_sub(2);
+
+        if self.selected < self.offset {
+            self.offset = self.selected;
+        } else if body > 0 && self.selected >= self.offset + body {
+            self.offset = self.selected + 1 - body;
+        }
+
+        // every line is padded to the full width, so there is no need to clear the screen first.
+        queue!(out, MoveTo(0, 0))?;
+        let waiting = self
+            .wfg
+            .sessions()
+            .filter(|&s| !self.wfg.blockers(s).is_empty())
+            .count();
+        let mut header = format!(
+            "waitforgraph - {} sessions, {} waiting, {} cycles - refreshed {}s ago",
+            self.wfg.sessions().count(),
+            waiting,
+            self.wfg.cycles().len(),
+            self.refreshed.elapsed().as_secs()
+        );
+        if self.refreshing {
+            header.push_str(" - refreshing...");
+        }
+        if let Some(err) = &self.error {
+            header.push_str(format!(" - refresh failed: {}", err).as_str());
+        }
+        queue!(
+            out,
+            SetAttribute(Attribute::Bold),
+            Print(fit(header.as_str(), width))
+        )?;
+        queue!(out, SetAttribute(Attribute::Reset))?;
+
+        let details = self.details(right);
+        for y in 0..body {
+            queue!(out, MoveTo(0, (y + 1) as u16))?;
+            let idx = self.offset + y;
+            if let Some(row) = self.rows.get(idx) {
+                if self.in_cycle.contains(&row.sessid) {
+                    queue!(out, SetForegroundColor(Color::Red))?;
+                }
+                if idx == self.selected {
+                    queue!(out, SetAttribute(Attribute::Reverse))?;
+                }
+                queue!(out, Print(fit(self.tree_line(row).as_str(), left)))?;
+                queue!(out, SetAttribute(Attribute::Reset), ResetColor)?;
+            } else {
+                queue!(out, Print(fit("", left)))?;
+            }
+            let line = details.get(y).map(String::as_str).unwrap_or("");
+            queue!(out, Print("│ "), Print(fit(line, right)))?;
+        }
+
+        queue!(
+            out,
+            MoveTo(0, height.saturating_sub(1) as u16),
+            SetAttribute(Attribute::Reverse),
+            Print(fit(HELP, width)),
+            SetAttribute(Attribute::Reset)
+        )?;
+        out.flush()
+    }
+}
+
+pub fn run(cli: GPDBCli, interval: Duration, conflict_policy: ConflictPolicy) -> io::Result<()> {
+    let mut app = App::new(cli, conflict_policy);
+    let _terminal = Terminal::new()?;
+    let mut stdout = io::stdout();
+    let mut next_refresh = Instant::now() + interval;
+    loop {
+        app.poll_refresh();
+        app.draw(&mut stdout)?;
+        let now = Instant::now();
+        if now >= next_refresh {
+            app.refresh();
+            next_refresh = now + interval;
+            continue;
+        }
+        // wake up at least once a second to keep "refreshed Ns ago" up to date, more often while
+        // waiting for the fetcher.
+        let tick = if app.refreshing {
+            Duration::from_millis(100)
+        } else {
+            Duration::from_secs(1)
+        };
+        let timeout = (next_refresh - now).min(tick);
+        if !event::poll(timeout)? {
+            continue;
+        }
+        let key = match event::read()? {
+            Event::Key(key) if key.kind == KeyEventKind::Press => key,
+            _ => continue,
+        };
+        match key.code {
+            KeyCode::Char('q') | KeyCode::Esc => return Ok(()),
+            KeyCode::Char('c') if key.modifiers.contains(KeyModifiers::CONTROL) => return Ok(()),
+            KeyCode::Char('j') | KeyCode::Down => {
+                app.selected = (app.selected + 1).min(app.rows.len().saturating_sub(1))
+            }
+            KeyCode::Char('k') | KeyCode::Up => app.selected = app.selected.saturating_sub(1),
+            KeyCode::Char(' ') | KeyCode::Enter => app.collapse(None),
+            KeyCode::Left => app.collapse(Some(true)),
+            KeyCode::Right => app.collapse(Some(false)),
+            KeyCode::Char('r') => app.jump_to_root(),
+            KeyCode::Char('R') => {
+                app.refresh();
+                next_refresh = Instant::now() + interval;
+            }
+            _ => {}
+        }
+    }
+}
@@ -0,0 +1,182 @@
+/*
+Copyright 2020 <盏一 w@hidva.com>
+Licensed under the Apache License, Version 2.0 (the "License");
+you may not use this file except in compliance with the License.
+You may obtain a copy of the License at
+
+http://www.apache.org/licenses/LICENSE-2.0
+
+Unless required by applicable law or agreed to in writing, software
+distributed under the License is distributed on an "AS IS" BASIS,
+WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+See the License for the specific language governing permissions and
+limitations under the License.
+*/
+use crate::activity::*;
+use crate::fmt_age;
+use crate::graph::WFGraph;
+use crate::lock::SessionId;
+use std::str;
+
+// What cancelling a session costs, see WFGraph::cancel_set().
+#[derive(PartialEq, Eq, Clone, Copy, Debug, Default)]
+pub enum CancelWeight {
+    // every session costs the same, cancel as few as possible.
+    #[default]
+    Count,
+    // the work of older transactions is lost, spare them.
+    XactAge,
+    // spare the statements running for a long time.
+    QueryAge,
+}
+
+impl str::FromStr for CancelWeight {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "count" => Ok(CancelWeight::Count),
+            "xact-age" => Ok(CancelWeight::XactAge),
+            "query-age" => Ok(CancelWeight::QueryAge),
+            _ => Err(format!(
+                "UnknownCancelWeight={}; expected count, xact-age or query-age",
+                s
+            )),
+        }
+    }
+}
+
+// The sessions to cancel to break every cycle and unblock the chosen waiters, and how.
+pub struct CancelPlan<'a> {
+    wfg: &'a WFGraph,
+    activity: ActivityInfo,
+    unblock: Vec<SessionId>,
+    pub cancel: Vec<SessionId>,
+}
+
+impl<'a> CancelPlan<'a> {
+    pub fn new(
+        wfg: &'a WFGraph,
+        activity: ActivityInfo,
+        unblock: Vec<SessionId>,
+        weight: CancelWeight,
+    ) -> CancelPlan<'a> {
+        let cost = |sessid: SessionId| {
+            let activity = activity.sessions.get(&sessid);
+            let age = match weight {
+                CancelWeight::Count => return 1,
+                CancelWeight::XactAge => activity.and_then(|a| a.xact_age),
+                CancelWeight::QueryAge => activity.and_then(|a| a.query_age),
+            };
+            age.unwrap_or(0).max(0) as u64
+        };
+        let cancel = wfg.cancel_set(&unblock, cost);
+        CancelPlan {
+            wfg,
+            activity,
+            unblock,
+            cancel,
+        }
+    }
+
+    // Why sessid is in the plan.
+    fn reasons(&self, sessid: SessionId) -> Vec<String> {
+        let mut reasons = vec![];
+        for cycle in self.wfg.cycles() {
+            if cycle.contains(&sessid) {
+                let cycle: Vec<String> = cycle.iter().map(SessionId::to_string).collect();
+                reasons.push(format!("in the cycle {}", cycle.join(", ")));
+            }
+        }
+        let blocked: Vec<String> = self
+            .wfg
+            .blocking(sessid)
+            .into_iter()
+            .filter(|s| self.unblock.contains(s))
+            .map(|s| s.to_string())
+            .collect();
+        if !blocked.is_empty() {
+            reasons.push(format!("blocks {}", blocked.join(", ")));
+        }
+        reasons
+    }
+
+    // Cancelling does not help a session idle in transaction, it has no statement to cancel.
+    // Prepared transactions are finished by COMMIT/ROLLBACK PREPARED only.
+    fn command(&self, sessid: SessionId) -> String {
+        if let Some(prepared) = self.wfg.prepared(sessid) {
+            return format!(
+                "rollback prepared '{}'; -- or commit prepared, in database {}",
+                prepared.gid.replace('\'', "''"),
+                prepared.database.as_deref().unwrap_or("?")
+            );
+        }
+        let activity = match self.activity.sessions.get(&sessid) {
+            Some(activity) => activity,
+            None => return String::from("-- no backend found on the coordinator"),
+        };
+        let pid = match activity.pid {
+            Some(pid) => pid,
+            None => return String::from("-- pid unknown"),
+        };
+        if activity
+            .state
+            .as_deref()
+            .is_some_and(|state| state.starts_with("idle"))
+        {
+            format!("select pg_terminate_backend({});", pid)
+        } else {
+            format!("select pg_cancel_backend({});", pid)
+        }
+    }
+
+    fn desc_session(&self, sessid: SessionId) -> String {
+        match self.activity.sessions.get(&sessid) {
+            Some(activity) => format!(
+                "{}({}, {}, in transaction for {})",
+                self.wfg.node_label(sessid),
+                activity.usename.as_deref().unwrap_or("?"),
+                activity.state.as_deref().unwrap_or("?"),
+                fmt_age(activity.xact_age)
+            ),
+            None => self.wfg.node_label(sessid),
+        }
+    }
+
+    pub fn render(&self) -> String {
+        if self.cancel.is_empty() {
+            return String::from("nothing to cancel");
+        }
+        let mut out = vec![format!("sessions to cancel: {}", self.cancel.len())];
+        for (idx, &sessid) in self.cancel.iter().enumerate() {
+            out.push(format!(
+                "{}. session {}: {}",
+                idx + 1,
+                self.desc_session(sessid),
+                self.reasons(sessid).join("; ")
+            ));
+            out.push(format!("   {}", self.command(sessid)));
+        }
+        // waiting for each other, unblocking one means cancelling the other.
+        let stuck: Vec<String> = self
+            .unblock
+            .iter()
+            .filter(|&&s| {
+                self.wfg
+                    .blockers(s)
+                    .iter()
+                    .any(|b| !self.cancel.contains(b))
+            })
+            .map(SessionId::to_string)
+            .collect();
+        if !stuck.is_empty() {
+            out.push(format!(
+                "{} still wait for other sessions to unblock",
+                stuck.join(", ")
+            ));
+        }
+        out.push(String::from(
+            "the graph changes while you go, run waitforgraph again after each step",
+        ));
+        out.join("\n")
+    }
+}
@@ -12,131 +12,921 @@ WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
 See the License for the specific language governing permissions and
 limitations under the License.
 */
+use postgres::config::Host;
+use postgres::error::SqlState;
 use postgres::{Client, Config, NoTls, SimpleQueryMessage};
+use std::collections::{HashMap, HashSet};
+use std::path::PathBuf;
+use std::sync::mpsc;
+use std::thread;
+use std::time::Duration;
+use std::{error, fmt, io, str};
 
-#[derive(Debug)]
+mod connstr;
+mod libpq;
+#[cfg(feature = "tls")]
+mod tls;
+
+// What the server can do, decided by GPDBVersion::new from the banner.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Capabilities {
+    // pg_locks.virtualxid, PostgreSQL 8.3, i.e. Greenplum 5.
+    pub virtualxid: bool,
+    // pg_locks.gp_segment_id and pg_locks.mppsessionid, every Greenplum derived product.
+    pub gp_segment_id: bool,
+    // the global deadlock detector and gp_dist_wait_status(), Greenplum 6.
+    pub gdd: bool,
+    // lock_timeout, PostgreSQL 9.3.
+    pub lock_timeout: bool,
+    // pg_stat_activity.pid/state/query instead of procpid/current_query, PostgreSQL 9.2.
+    pub activity_pid: bool,
+    // gp_role instead of gp_session_role, PostgreSQL 12, i.e. Greenplum 7.
+    pub gp_role: bool,
+    // pg_describe_object(), PostgreSQL 9.1.
+    pub describe_object: bool,
+    // pg_locks.fastpath, PostgreSQL 9.2.
+    pub fastpath: bool,
+    // pg_locks.waitstart, PostgreSQL 14, but backported by some forks. GPDBCli::connect asks the
+    // catalog for the columns of pg_locks and pg_stat_activity instead of trusting the banner.
+    pub waitstart: bool,
+    // pg_blocking_pids(), PostgreSQL 9.6, i.e. Greenplum 7.
+    pub blocking_pids: bool,
+    // resource groups, pg_stat_activity.rsgname, Greenplum 5.
+    pub resgroup: bool,
+}
+
+#[derive(Debug, Clone)]
 pub struct GPDBVersion {
-    verstr: String,
+    // the whole `select version()`.
+    pub verstr: String,
+    // Greenplum Database, Cloudberry Database, Apache Cloudberry, ... or PostgreSQL.
+    pub product: String,
+    // version of the product.
     pub semver: semver::Version,
+    // version of the PostgreSQL it is based on.
+    pub pgver: semver::Version,
+    pub caps: Capabilities,
+}
+
+// 4.3.99.00, 7.0.0-beta.1, 1.5.4+dev.12, 6.20 => 4.3.99, 7.0.0, 1.5.4, 6.20.0
+fn parse_version(verstr: &str) -> Option<semver::Version> {
+    let end = verstr
+        .find(|c: char| !c.is_ascii_digit() && c != '.')
+        .unwrap_or(verstr.len());
+    let mut parts = verstr[..end]
+        .split('.')
+        .take(3)
+        .map(|part| part.parse::<u64>().ok());
+    let major = parts.next()??;
+    let minor = parts.next().flatten().unwrap_or(0);
+    let patch = parts.next().flatten().unwrap_or(0);
+    Some(semver::Version::new(major, minor, patch))
+}
+
+// The first token of `s` that parses as a version, and everything before it.
+fn find_version(s: &str) -> Option<(&str, semver::Version)> {
+    let mut pos = 0;
+    for word in s.split(' ') {
+        if let Some(ver) = parse_version(word) {
+            return Some((s[..pos].trim(), ver));
+        }
+        pos += word.len() + 1;
+    }
+    None
+}
+
+// PostgreSQL each Greenplum major version is based on, for banners lacking it.
+fn greenplum_pgver(major: u64) -> semver::Version {
+    match major {
+        0..=4 => semver::Version::new(8, 2, 15),
+        5 => semver::Version::new(8, 3, 23),
+        6 => semver::Version::new(9, 4, 26),
+        _ => semver::Version::new(12, 12, 0),
+    }
 }
 
 impl GPDBVersion {
-    // str: (Greenplum Database 6.3.0 build dev)
-    fn new(gpver: &str) -> GPDBVersion {
-        let marker = "(Greenplum Database ";
+    // str: PostgreSQL 9.4.24 (Greenplum Database 6.3.0 build dev) on x86_64-unknown-linux-gnu, ...
+    //      PostgreSQL 14.4 (Apache Cloudberry 1.6.0 build 1) on x86_64-pc-linux-gnu, ...
+    //      PostgreSQL 12.12 (Greenplum Database 7.0.0-beta.1 build commit:...) on ...
+    // Panics only if there is no version number at all.
+    pub fn new(gpver: &str) -> GPDBVersion {
         let errstr = format!("UnexpectedVersion={}", gpver);
-        let ver_start = gpver.find(marker).expect(errstr.as_str()) + marker.len();
-        let ver_end = gpver.find(')').expect(errstr.as_str());
-        let verstr = gpver[ver_start..ver_end].to_string();
-        let semverstr = if let Some(ver_end) = verstr.find(' ') {
-            &verstr[..ver_end]
-        } else {
-            verstr.as_str()
+        let pgver = gpver.strip_prefix("PostgreSQL ").and_then(parse_version);
+        // the derived product, its version and build are in the first parentheses, distributions
+        // of the vanilla PostgreSQL put their own version there, e.g. (Debian 16.2-1.pgdg120+2).
+        let derived = gpver
+            .find('(')
+            .and_then(|start| {
+                let end = gpver[start..].find(')')? + start;
+                let inner = &gpver[start + 1..end];
+                inner.contains(" build").then(|| find_version(inner))?
+            })
+            .filter(|(product, _)| !product.is_empty());
+        let (product, semver, pgver) = match (derived, pgver) {
+            (Some((product, semver)), Some(pgver)) => (product, semver, pgver),
+            (Some((product, semver)), None) => {
+                let pgver = greenplum_pgver(semver.major);
+                (product, semver, pgver)
+            }
+            (None, Some(pgver)) => ("PostgreSQL", pgver.clone(), pgver),
+            (None, None) => {
+                let (product, semver) = find_version(gpver).expect(errstr.as_str());
+                let pgver = greenplum_pgver(semver.major);
+                (product, semver, pgver)
+            }
+        };
+        let greenplum = product != "PostgreSQL";
+        let atleast = |major, minor| (pgver.major, pgver.minor) >= (major, minor);
+        let caps = Capabilities {
+            virtualxid: atleast(8, 3),
+            gp_segment_id: greenplum,
+            gdd: greenplum && atleast(9, 4),
+            lock_timeout: atleast(9, 3),
+            activity_pid: atleast(9, 2),
+            gp_role: atleast(12, 0),
+            describe_object: atleast(9, 1),
+            fastpath: atleast(9, 2),
+            waitstart: atleast(14, 0),
+            blocking_pids: atleast(9, 6),
+            resgroup: greenplum && atleast(8, 3),
         };
-        // semverstr may be 4.3.99.00
-        let semverpart: Vec<&str> = semverstr.split('.').collect();
-        let semverstr = semverpart[..3].join(".");
-        let semver = semver::Version::parse(semverstr.as_str()).expect(errstr.as_str());
-        GPDBVersion { verstr, semver }
+        GPDBVersion {
+            verstr: gpver.to_string(),
+            product: product.to_string(),
+            semver,
+            pgver,
+            caps,
+        }
     }
 }
 
+impl fmt::Display for GPDBVersion {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.caps.gp_segment_id {
+            write!(
+                f,
+                "{} {} (PostgreSQL {})",
+                self.product, self.semver, self.pgver
+            )
+        } else {
+            write!(f, "{} {}", self.product, self.semver)
+        }
+    }
+}
+
+// connstr wins, then the service file, then the environment variable, then the default.
 fn autofill_opt(
     cfg: &mut Config,
+    service: &HashMap<String, String>,
     exists: fn(&Config) -> bool,
     set: fn(&mut Config, &str),
+    optkey: &str,
     optenv: &str,
-    optdef: &str,
+    optdef: Option<&str>,
 ) {
     if exists(cfg) {
         return;
     }
 
     let val: String;
-    if let Ok(envval) = std::env::var(optenv) {
+    if let Some(serviceval) = service.get(optkey) {
+        val = serviceval.clone();
+    } else if let Ok(envval) = std::env::var(optenv) {
         val = envval;
-    } else {
+    } else if let Some(optdef) = optdef {
         val = String::from(optdef);
+    } else {
+        return;
     }
 
     set(cfg, val.as_str());
 }
 
-fn get_config(connstr: &str) -> Config {
+// Like libpq, prefer the Unix-domain socket. Greenplum puts it in /tmp.
+fn default_host(port: u16) -> String {
+    for dir in ["/tmp", "/var/run/postgresql"].iter() {
+        if std::path::Path::new(dir)
+            .join(format!(".s.PGSQL.{}", port))
+            .exists()
+        {
+            return String::from(*dir);
+        }
+    }
+    String::from("/tmp")
+}
+
+// None means no timeout, or the server default for statement and lock timeouts.
+#[derive(Default, Clone)]
+pub struct Timeouts {
+    pub connect: Option<Duration>,
+    // connect was given by the user, it wins over the service file and PGCONNECT_TIMEOUT.
+    pub connect_set: bool,
+    pub statement: Option<Duration>,
+    pub lock: Option<Duration>,
+}
+
+#[derive(Debug)]
+pub enum GPDBError {
+    // connect timeout, statement_timeout or lock_timeout expired.
+    Timeout(String),
+    // invalid ssl settings, or TLS is not compiled in.
+    Ssl(String),
+    Other(postgres::Error),
+}
+
+impl GPDBError {
+    pub fn is_timeout(&self) -> bool {
+        matches!(self, GPDBError::Timeout(_))
+    }
+}
+
+impl From<postgres::Error> for GPDBError {
+    fn from(err: postgres::Error) -> Self {
+        let timeout = match err.code() {
+            Some(code) => {
+                *code == SqlState::QUERY_CANCELED || *code == SqlState::LOCK_NOT_AVAILABLE
+            }
+            None => error::Error::source(&err)
+                .and_then(|e| e.downcast_ref::<io::Error>())
+                .is_some_and(|e| e.kind() == io::ErrorKind::TimedOut),
+        };
+        if timeout {
+            GPDBError::Timeout(err.to_string())
+        } else {
+            GPDBError::Other(err)
+        }
+    }
+}
+
+impl fmt::Display for GPDBError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            GPDBError::Timeout(msg) => write!(f, "Timeout; {}", msg),
+            GPDBError::Ssl(msg) => write!(f, "Ssl; {}", msg),
+            GPDBError::Other(err) => write!(f, "{}", err),
+        }
+    }
+}
+
+impl error::Error for GPDBError {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match self {
+            GPDBError::Timeout(_) | GPDBError::Ssl(_) => None,
+            GPDBError::Other(err) => Some(err),
+        }
+    }
+}
+
+// libpq's sslmode, postgres::config::SslMode only knows disable, prefer and require.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SslMode {
+    Disable,
+    Allow,
+    Prefer,
+    Require,
+    VerifyCa,
+    VerifyFull,
+}
+
+impl str::FromStr for SslMode {
+    type Err = GPDBError;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "disable" => Ok(SslMode::Disable),
+            "allow" => Ok(SslMode::Allow),
+            "prefer" => Ok(SslMode::Prefer),
+            "require" => Ok(SslMode::Require),
+            "verify-ca" => Ok(SslMode::VerifyCa),
+            "verify-full" => Ok(SslMode::VerifyFull),
+            _ => Err(GPDBError::Ssl(format!("invalid sslmode \"{}\"", s))),
+        }
+    }
+}
+
+impl fmt::Display for SslMode {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SslMode::Disable => write!(f, "disable"),
+            SslMode::Allow => write!(f, "allow"),
+            SslMode::Prefer => write!(f, "prefer"),
+            SslMode::Require => write!(f, "require"),
+            SslMode::VerifyCa => write!(f, "verify-ca"),
+            SslMode::VerifyFull => write!(f, "verify-full"),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct SslConfig {
+    pub mode: SslMode,
+    pub rootcert: Option<String>,
+    pub cert: Option<String>,
+    pub key: Option<String>,
+}
+
+const SSL_PARAMS: &[&str] = &["sslmode", "sslrootcert", "sslcert", "sslkey"];
+
+// Keywords libpq knows but postgres::Config does not.
+const LIBPQ_PARAMS: &[&str] = &[
+    "sslmode",
+    "sslrootcert",
+    "sslcert",
+    "sslkey",
+    "service",
+    "passfile",
+];
+
+// connstr first, then the PGSSL* environment variable, then libpq's default file under
+// ~/.postgresql if it exists.
+fn get_ssl_file(
+    params: &HashMap<String, String>,
+    key: &str,
+    env: &str,
+    def: &str,
+) -> Option<String> {
+    if let Some(val) = params.get(key) {
+        return Some(val.clone());
+    }
+    if let Ok(val) = std::env::var(env) {
+        return Some(val);
+    }
+    let home = std::env::var("HOME").ok()?;
+    let path: PathBuf = [home.as_str(), ".postgresql", def].iter().collect();
+    if path.exists() {
+        path.to_str().map(String::from)
+    } else {
+        None
+    }
+}
+
+fn get_ssl_config(params: &HashMap<String, String>) -> SslConfig {
+    let mode = match params.get("sslmode") {
+        Some(mode) => mode.clone(),
+        None => std::env::var("PGSSLMODE").unwrap_or_else(|_| String::from("prefer")),
+    };
+    SslConfig {
+        mode: mode.parse().unwrap_or_else(|e| panic!("{}", e)),
+        rootcert: get_ssl_file(params, "sslrootcert", "PGSSLROOTCERT", "root.crt"),
+        cert: get_ssl_file(params, "sslcert", "PGSSLCERT", "postgresql.crt"),
+        key: get_ssl_file(params, "sslkey", "PGSSLKEY", "postgresql.key"),
+    }
+}
+
+fn get_config(connstr: &str, timeouts: &Timeouts) -> (Config, SslConfig) {
+    let (connstr, params) = connstr::split_params(connstr, LIBPQ_PARAMS);
+    let service = match params.get("service") {
+        Some(service) => libpq::read_service(service),
+        None => match std::env::var("PGSERVICE") {
+            Ok(service) => libpq::read_service(service.as_str()),
+            Err(_) => HashMap::new(),
+        },
+    };
+
+    // ssl settings from connstr win over the service file.
+    let mut sslparams: HashMap<String, String> = service
+        .iter()
+        .filter(|(k, _)| SSL_PARAMS.contains(&k.as_str()))
+        .map(|(k, v)| (k.clone(), v.clone()))
+        .collect();
+    sslparams.extend(
+        params
+            .iter()
+            .filter(|(k, _)| SSL_PARAMS.contains(&k.as_str()))
+            .map(|(k, v)| (k.clone(), v.clone())),
+    );
+    let ssl = get_ssl_config(&sslparams);
+
     let mut cfg: Config = connstr.parse().unwrap();
+    // allow is approximated by prefer.
+    cfg.ssl_mode(match ssl.mode {
+        SslMode::Disable => postgres::config::SslMode::Disable,
+        SslMode::Allow | SslMode::Prefer => postgres::config::SslMode::Prefer,
+        _ => postgres::config::SslMode::Require,
+    });
+    // connect_timeout in connstr wins over everything else.
+    if cfg.get_connect_timeout().is_none() {
+        let configured = service
+            .get("connect_timeout")
+            .cloned()
+            .or_else(|| std::env::var("PGCONNECT_TIMEOUT").ok());
+        let timeout = match configured {
+            // libpq takes non-positive values as no timeout, and so do we.
+            Some(secs) if !timeouts.connect_set => Some(secs.parse::<i64>().unwrap())
+                .filter(|&secs| secs > 0)
+                .map(|secs| Duration::from_secs(secs as u64)),
+            _ => timeouts.connect,
+        };
+        if let Some(timeout) = timeout {
+            cfg.connect_timeout(timeout);
+        }
+    }
     autofill_opt(
         &mut cfg,
-        |c| c.get_hosts().len() > 0,
+        &service,
+        |c| !c.get_ports().is_empty(),
         |c, v| {
-            c.host(v);
+            c.port(v.parse().unwrap());
         },
-        "PGHOST",
-        "127.0.0.1",
+        "port",
+        "PGPORT",
+        Some("5432"),
     );
+    let port = cfg.get_ports()[0];
     autofill_opt(
         &mut cfg,
-        |c| c.get_ports().len() > 0,
+        &service,
+        |c| !c.get_hosts().is_empty(),
         |c, v| {
-            c.port(v.parse().unwrap());
+            c.host(v);
         },
-        "PGPORT",
-        "5432",
+        "host",
+        "PGHOST",
+        Some(default_host(port).as_str()),
     );
+    let osuser = std::env::var("USER")
+        .or_else(|_| std::env::var("LOGNAME"))
+        .unwrap_or_else(|_| String::from("gpadmin"));
     autofill_opt(
         &mut cfg,
+        &service,
         |c| c.get_user().is_some(),
         |c, v| {
             c.user(v);
         },
+        "user",
         "PGUSER",
-        std::env::var("USER").unwrap().as_str(),
+        Some(osuser.as_str()),
     );
     let user = String::from(cfg.get_user().unwrap());
     autofill_opt(
         &mut cfg,
+        &service,
         |c| c.get_dbname().is_some(),
         |c, v| {
             c.dbname(v);
         },
+        "dbname",
         "PGDATABASE",
-        user.as_str(),
+        Some(user.as_str()),
     );
     autofill_opt(
         &mut cfg,
+        &service,
         |c| c.get_application_name().is_some(),
         |c, v| {
             c.application_name(v);
         },
+        "application_name",
         "PGAPPNAME",
-        "hidva/wait-for-graph",
+        Some("hidva/wait-for-graph"),
     );
-    cfg
+    autofill_opt(
+        &mut cfg,
+        &service,
+        |c| c.get_options().is_some(),
+        |c, v| {
+            c.options(v);
+        },
+        "options",
+        "PGOPTIONS",
+        None,
+    );
+    autofill_opt(
+        &mut cfg,
+        &service,
+        |c| c.get_password().is_some(),
+        |c, v| {
+            c.password(v);
+        },
+        "password",
+        "PGPASSWORD",
+        None,
+    );
+    if cfg.get_password().is_none() {
+        let host = match &cfg.get_hosts()[0] {
+            Host::Tcp(host) => host.clone(),
+            Host::Unix(path) => path.display().to_string(),
+        };
+        let passfile = params.get("passfile").or_else(|| service.get("passfile"));
+        if let Some(password) = libpq::read_pgpass(
+            passfile.map(String::as_str),
+            host.as_str(),
+            port,
+            cfg.get_dbname().unwrap(),
+            user.as_str(),
+        ) {
+            cfg.password(password);
+        }
+    }
+    (cfg, ssl)
+}
+
+// The option that puts a connection into utility mode.
+pub fn utility_option(caps: &Capabilities) -> &'static str {
+    if caps.gp_role {
+        "-c gp_role=utility"
+    } else {
+        "-c gp_session_role=utility"
+    }
+}
+
+// Same connection parameters as `cfg`, but to host:port in utility mode.
+fn get_utility_config(cfg: &Config, ver: &GPDBVersion, host: &str, port: u16) -> Config {
+    let mut segcfg = Config::new();
+    segcfg.host(host).port(port);
+    if let Some(user) = cfg.get_user() {
+        segcfg.user(user);
+    }
+    if let Some(password) = cfg.get_password() {
+        segcfg.password(password);
+    }
+    if let Some(dbname) = cfg.get_dbname() {
+        segcfg.dbname(dbname);
+    }
+    if let Some(appname) = cfg.get_application_name() {
+        segcfg.application_name(appname);
+    }
+    if let Some(&timeout) = cfg.get_connect_timeout() {
+        segcfg.connect_timeout(timeout);
+    }
+    let role = utility_option(&ver.caps);
+    let options = match cfg.get_options() {
+        Some(options) => format!("{} {}", options, role),
+        None => String::from(role),
+    };
+    segcfg.options(options.as_str());
+    segcfg.ssl_mode(cfg.get_ssl_mode());
+    segcfg
+}
+
+// A primary segment, or the coordinator (content -1), from gp_segment_configuration.
+#[derive(Clone)]
+pub struct Segment {
+    pub content: i64,
+    pub hostname: String,
+    pub port: u16,
+    // 'p' or 'm', a primary with preferred_role 'm' is a mirror promoted after a failover.
+    pub role: String,
+    pub preferred_role: String,
+    // None if unknown.
+    pub datadir: Option<String>,
+}
+
+impl fmt::Display for Segment {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}:{}", self.hostname, self.port)?;
+        if let Some(datadir) = &self.datadir {
+            write!(f, " {}", datadir)?;
+        }
+        if self.role != self.preferred_role {
+            write!(f, ", role {} preferred {}", self.role, self.preferred_role)?;
+        }
+        Ok(())
+    }
+}
+
+// "coordinator", or "segment N" and where it is if `segment` is known.
+pub fn desc_segment(segid: i64, segment: Option<&Segment>) -> String {
+    match segment {
+        _ if segid < 0 => String::from("coordinator"),
+        Some(segment) => format!("segment {} ({})", segid, segment),
+        None => format!("segment {}", segid),
+    }
+}
+
+// What GPDBCli::new_utility needs to connect to a segment, it can be sent to another thread.
+pub struct UtilityConfig {
+    cfg: Config,
+    ssl: SslConfig,
+    ver: GPDBVersion,
 }
 
 pub struct GPDBCli {
     cli: Client,
+    cfg: Config,
+    ssl: SslConfig,
     pub ver: GPDBVersion,
 }
 
+// Config::connect_timeout only covers establishing the socket, but the startup may hang too,
+// e.g. on a locked catalog. Bound the whole thing.
+fn connect(cfg: &Config, ssl: &SslConfig) -> Result<Client, GPDBError> {
+    let timeout = match cfg.get_connect_timeout() {
+        Some(&timeout) => timeout,
+        None => return connect_tls(cfg, ssl),
+    };
+    let (tx, rx) = mpsc::channel();
+    let cfg = cfg.clone();
+    let ssl = ssl.clone();
+    thread::spawn(move || {
+        let _ = tx.send(connect_tls(&cfg, &ssl));
+    });
+    match rx.recv_timeout(timeout) {
+        Ok(res) => res,
+        Err(_) => Err(GPDBError::Timeout(format!(
+            "connection not established within {:?}",
+            timeout
+        ))),
+    }
+}
+
+#[cfg(feature = "tls")]
+fn connect_tls(cfg: &Config, ssl: &SslConfig) -> Result<Client, GPDBError> {
+    if ssl.mode == SslMode::Disable {
+        return Ok(cfg.connect(NoTls)?);
+    }
+    Ok(cfg.connect(tls::connector(ssl)?)?)
+}
+
+#[cfg(not(feature = "tls"))]
+fn connect_tls(cfg: &Config, ssl: &SslConfig) -> Result<Client, GPDBError> {
+    match ssl.mode {
+        SslMode::Disable | SslMode::Allow | SslMode::Prefer => Ok(cfg.connect(NoTls)?),
+        mode => Err(GPDBError::Ssl(format!(
+            "sslmode={} needs waitforgraph built with the tls feature",
+            mode
+        ))),
+    }
+}
+
+fn view_columns(cli: &mut Client, view: &str) -> Result<HashSet<String>, GPDBError> {
+    let qres = cli.simple_query(
+        format!(
+            "select attname from pg_attribute \
+            where attrelid = '{}'::regclass and attnum > 0",
+            view
+        )
+        .as_str(),
+    )?;
+    Ok(qres
+        .iter()
+        .filter_map(|msg| match msg {
+            SimpleQueryMessage::Row(row) => row.get(0).map(String::from),
+            _ => None,
+        })
+        .collect())
+}
+
+// Forks backport columns now and then, trust the catalog over the banner for what we select.
+fn probe_views(cli: &mut Client, ver: &mut GPDBVersion) -> Result<(), GPDBError> {
+    let columns = view_columns(cli, "pg_locks")?;
+    ver.caps.virtualxid = columns.contains("virtualxid");
+    ver.caps.fastpath = columns.contains("fastpath");
+    ver.caps.waitstart = columns.contains("waitstart");
+    let columns = view_columns(cli, "pg_stat_activity")?;
+    ver.caps.resgroup = columns.contains("rsgname");
+    Ok(())
+}
+
+fn set_timeouts(cli: &mut Client, ver: &GPDBVersion, timeouts: &Timeouts) -> Result<(), GPDBError> {
+    if let Some(timeout) = timeouts.statement {
+        cli.batch_execute(format!("set statement_timeout = {}", timeout.as_millis()).as_str())?;
+    }
+    if let (Some(timeout), true) = (timeouts.lock, ver.caps.lock_timeout) {
+        cli.batch_execute(format!("set lock_timeout = {}", timeout.as_millis()).as_str())?;
+    }
+    Ok(())
+}
+
 impl GPDBCli {
-    fn query_val(cli: &mut Client, query: &str) -> String {
-        let ret = &cli.simple_query(query).unwrap()[0];
+    fn query_val(cli: &mut Client, query: &str) -> Result<String, GPDBError> {
+        let ret = &cli.simple_query(query)?[0];
         if let SimpleQueryMessage::Row(row) = ret {
-            String::from(row.get(0).unwrap())
+            Ok(String::from(row.get(0).unwrap()))
         } else {
             panic!("UnexpectedResult; query={} ret=CommandComplete", query)
         }
     }
 
     pub fn new(connstr: &str) -> GPDBCli {
-        let cfg = get_config(connstr);
-        let mut cli = cfg.connect(NoTls).unwrap();
-        let ver =
-            GPDBVersion::new(GPDBCli::query_val(&mut cli, "select pg_catalog.version()").as_str());
-        GPDBCli { cli, ver }
+        GPDBCli::connect(connstr, &Timeouts::default()).unwrap_or_else(|e| panic!("{}", e))
+    }
+
+    pub fn connect(connstr: &str, timeouts: &Timeouts) -> Result<GPDBCli, GPDBError> {
+        let (cfg, ssl) = get_config(connstr, timeouts);
+        let mut cli = connect(&cfg, &ssl)?;
+        let mut ver =
+            GPDBVersion::new(GPDBCli::query_val(&mut cli, "select pg_catalog.version()")?.as_str());
+        probe_views(&mut cli, &mut ver)?;
+        set_timeouts(&mut cli, &ver, timeouts)?;
+        Ok(GPDBCli { cli, cfg, ssl, ver })
+    }
+
+    // The columns of a table or view, an error if it does not exist.
+    pub fn columns(&mut self, view: &str) -> Result<HashSet<String>, GPDBError> {
+        view_columns(&mut self.cli, view)
+    }
+
+    // The segments serving now, Greenplum only. Before Greenplum 6 the data directories are in
+    // pg_filespace_entry.
+    pub fn primaries(&mut self) -> Result<Vec<Segment>, GPDBError> {
+        let columns = self.columns("gp_segment_configuration")?;
+        let qstr = if columns.contains("datadir") {
+            "select content, hostname, port, role, preferred_role, datadir \
+            from gp_segment_configuration where role = 'p' order by content"
+        } else {
+            "select c.content, c.hostname, c.port, c.role, c.preferred_role, \
+            f.fselocation as datadir \
+            from gp_segment_configuration c left join pg_filespace_entry f \
+            on f.fsedbid = c.dbid \
+            and f.fsefsoid = (select oid from pg_filespace where fsname = 'pg_system') \
+            where c.role = 'p' order by c.content"
+        };
+        let mut segments = vec![];
+        for rowres in self.try_query(qstr)?.iter() {
+            if let SimpleQueryMessage::Row(row) = rowres {
+                segments.push(Segment {
+                    content: row.get("content").unwrap().parse().unwrap(),
+                    hostname: String::from(row.get("hostname").unwrap()),
+                    port: row.get("port").unwrap().parse().unwrap(),
+                    role: String::from(row.get("role").unwrap()),
+                    preferred_role: String::from(row.get("preferred_role").unwrap()),
+                    datadir: row.get("datadir").map(String::from),
+                });
+            }
+        }
+        Ok(segments)
+    }
+
+    pub fn get_utility_config(&self, host: &str, port: u16) -> UtilityConfig {
+        UtilityConfig {
+            cfg: get_utility_config(&self.cfg, &self.ver, host, port),
+            ssl: self.ssl.clone(),
+            ver: self.ver.clone(),
+        }
+    }
+
+    // Connect to a single segment (or the coordinator itself) in utility mode, `cfg` comes from
+    // get_utility_config(). The segment runs the same version as the coordinator, so we do not
+    // ask it again. `timeout` bounds both the connection and every statement, None means no
+    // timeouts but the connect timeout of the coordinator.
+    pub fn new_utility(
+        ucfg: UtilityConfig,
+        timeout: Option<Duration>,
+    ) -> Result<GPDBCli, GPDBError> {
+        let UtilityConfig { mut cfg, ssl, ver } = ucfg;
+        if let Some(timeout) = timeout {
+            cfg.connect_timeout(timeout);
+        }
+        let mut cli = connect(&cfg, &ssl)?;
+        let timeouts = Timeouts {
+            connect: timeout,
+            connect_set: true,
+            statement: timeout,
+            lock: timeout,
+        };
+        set_timeouts(&mut cli, &ver, &timeouts)?;
+        Ok(GPDBCli { cli, cfg, ssl, ver })
+    }
+
+    pub fn try_query(&mut self, query: &str) -> Result<Vec<SimpleQueryMessage>, GPDBError> {
+        Ok(self.cli.simple_query(query)?)
     }
 
     pub fn query(&mut self, query: &str) -> Vec<SimpleQueryMessage> {
-        self.cli.simple_query(query).unwrap()
+        self.try_query(query)
+            .unwrap_or_else(|e| panic!("{}; query={}", e, query))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ver(major: u64, minor: u64, patch: u64) -> semver::Version {
+        semver::Version::new(major, minor, patch)
+    }
+
+    #[test]
+    fn test_parse_version() {
+        assert_eq!(parse_version("4.3.99.00"), Some(ver(4, 3, 99)));
+        assert_eq!(parse_version("7.0.0-beta.1"), Some(ver(7, 0, 0)));
+        assert_eq!(parse_version("1.5.4+dev.12"), Some(ver(1, 5, 4)));
+        assert_eq!(parse_version("6.20"), Some(ver(6, 20, 0)));
+        assert_eq!(parse_version("16.2,"), Some(ver(16, 2, 0)));
+        assert_eq!(parse_version("Database"), None);
+        assert_eq!(parse_version(""), None);
+    }
+
+    #[test]
+    fn test_find_version() {
+        assert_eq!(
+            find_version("Greenplum Database 7.0.0-beta.1 build commit:c8a1b7c"),
+            Some(("Greenplum Database", ver(7, 0, 0)))
+        );
+        assert_eq!(
+            find_version("Apache Cloudberry 1.6.0 build 1"),
+            Some(("Apache Cloudberry", ver(1, 6, 0)))
+        );
+        assert_eq!(find_version("no version here"), None);
+    }
+
+    #[test]
+    fn test_greenplum4() {
+        let v = GPDBVersion::new(
+            "PostgreSQL 8.2.15 (Greenplum Database 4.3.99.00 build dev) on \
+            x86_64-unknown-linux-gnu, compiled by GCC gcc (GCC) 4.4.2 compiled on Nov 28 2019",
+        );
+        assert_eq!(v.product, "Greenplum Database");
+        assert_eq!(v.semver, ver(4, 3, 99));
+        assert_eq!(v.pgver, ver(8, 2, 15));
+        assert!(v.caps.gp_segment_id);
+        assert!(!v.caps.virtualxid);
+        assert!(!v.caps.gdd);
+        assert!(!v.caps.resgroup);
+        assert!(!v.caps.activity_pid);
+    }
+
+    #[test]
+    fn test_greenplum5() {
+        let v = GPDBVersion::new(
+            "PostgreSQL 8.3.23 (Greenplum Database 5.28.0 build commit:1a2b3c4d) on \
+            x86_64-pc-linux-gnu, compiled by GCC gcc (GCC) 6.4.0, 64-bit compiled on Jun 1 2020",
+        );
+        assert_eq!(v.product, "Greenplum Database");
+        assert_eq!(v.semver, ver(5, 28, 0));
+        assert_eq!(v.pgver, ver(8, 3, 23));
+        assert!(v.caps.virtualxid);
+        assert!(v.caps.resgroup);
+        assert!(!v.caps.gdd);
+        assert!(!v.caps.lock_timeout);
+    }
+
+    #[test]
+    fn test_greenplum6() {
+        let v = GPDBVersion::new(
+            "PostgreSQL 9.4.24 (Greenplum Database 6.3.0 build dev) on x86_64-unknown-linux-gnu, \
+            compiled by gcc (GCC) 6.4.0, 64-bit compiled on Jan  1 2020 00:00:00",
+        );
+        assert_eq!(v.product, "Greenplum Database");
+        assert_eq!(v.semver, ver(6, 3, 0));
+        assert_eq!(v.pgver, ver(9, 4, 24));
+        assert!(v.caps.gdd);
+        assert!(v.caps.lock_timeout);
+        assert!(v.caps.fastpath);
+        assert!(!v.caps.blocking_pids);
+        assert!(!v.caps.gp_role);
+    }
+
+    #[test]
+    fn test_greenplum7_beta() {
+        let v = GPDBVersion::new(
+            "PostgreSQL 12.12 (Greenplum Database 7.0.0-beta.1 build commit:c8a1b7c) on \
+            x86_64-pc-linux-gnu, compiled by gcc (GCC) 8.5.0 20210514 (Red Hat 8.5.0-10), 64-bit \
+            compiled on Feb  1 2023 00:00:00",
+        );
+        assert_eq!(v.product, "Greenplum Database");
+        assert_eq!(v.semver, ver(7, 0, 0));
+        assert_eq!(v.pgver, ver(12, 12, 0));
+        assert!(v.caps.gp_role);
+        assert!(v.caps.blocking_pids);
+        assert!(!v.caps.waitstart);
+    }
+
+    #[test]
+    fn test_cloudberry() {
+        let v = GPDBVersion::new(
+            "PostgreSQL 14.4 (Apache Cloudberry 1.6.0 build 1) on x86_64-pc-linux-gnu, \
+            compiled by gcc (GCC) 10.2.1 20210130 (Red Hat 10.2.1-11), 64-bit",
+        );
+        assert_eq!(v.product, "Apache Cloudberry");
+        assert_eq!(v.semver, ver(1, 6, 0));
+        assert_eq!(v.pgver, ver(14, 4, 0));
+        assert!(v.caps.gp_segment_id);
+        assert!(v.caps.gdd);
+        assert!(v.caps.waitstart);
+    }
+
+    #[test]
+    fn test_greenplum_without_pgver() {
+        let v = GPDBVersion::new("Greenplum Database 6.20.0 build commit:abcdef");
+        assert_eq!(v.product, "Greenplum Database");
+        assert_eq!(v.semver, ver(6, 20, 0));
+        assert_eq!(v.pgver, ver(9, 4, 26));
+    }
+
+    #[test]
+    fn test_debian_postgresql() {
+        let v = GPDBVersion::new(
+            "PostgreSQL 16.2 (Debian 16.2-1.pgdg120+2) on x86_64-pc-linux-gnu, \
+            compiled by gcc (Debian 12.2.0-14) 12.2.0, 64-bit",
+        );
+        assert_eq!(v.product, "PostgreSQL");
+        assert_eq!(v.semver, ver(16, 2, 0));
+        assert_eq!(v.pgver, ver(16, 2, 0));
+        assert!(!v.caps.gp_segment_id);
+        assert!(!v.caps.gdd);
+        assert!(!v.caps.resgroup);
+        assert!(v.caps.waitstart);
+    }
+
+    #[test]
+    fn test_windows_postgresql() {
+        // " build" is outside of any parentheses, so this is not taken for a derived product.
+        let v = GPDBVersion::new("PostgreSQL 16.2, compiled by Visual C++ build 1937, 64-bit");
+        assert_eq!(v.product, "PostgreSQL");
+        assert_eq!(v.pgver, ver(16, 2, 0));
+        assert!(!v.caps.gp_segment_id);
     }
 }
@@ -0,0 +1,218 @@
+/*
+Copyright 2020 <盏一 w@hidva.com>
+Licensed under the Apache License, Version 2.0 (the "License");
+you may not use this file except in compliance with the License.
+You may obtain a copy of the License at
+
+http://www.apache.org/licenses/LICENSE-2.0
+
+Unless required by applicable law or agreed to in writing, software
+distributed under the License is distributed on an "AS IS" BASIS,
+WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+See the License for the specific language governing permissions and
+limitations under the License.
+*/
+// Just enough of libpq's connection string syntax, keyword/value and URI, to take out the
+// parameters that postgres::Config does not understand.
+use std::collections::HashMap;
+
+fn is_uri(connstr: &str) -> bool {
+    connstr.starts_with("postgresql://") || connstr.starts_with("postgres://")
+}
+
+fn percent_decode(s: &str) -> String {
+    let bytes = s.as_bytes();
+    let mut out = vec![];
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'%' && i + 2 < bytes.len() {
+            let hex = std::str::from_utf8(&bytes[i + 1..i + 3]).ok();
+            if let Some(b) = hex.and_then(|hex| u8::from_str_radix(hex, 16).ok()) {
+                out.push(b);
+                i += 3;
+                continue;
+            }
+        }
+        out.push(bytes[i]);
+        i += 1;
+    }
+    String::from_utf8_lossy(&out).into_owned()
+}
+
+// key = value pairs, values may be single-quoted, backslash escapes quotes and backslashes.
+fn parse_keyvalue(connstr: &str) -> Vec<(String, String)> {
+    let mut params = vec![];
+    let mut chars = connstr.chars().peekable();
+    loop {
+        while chars.peek().is_some_and(|c| c.is_whitespace()) {
+            chars.next();
+        }
+        let mut key = String::new();
+        while let Some(&c) = chars.peek() {
+            if c == '=' || c.is_whitespace() {
+                break;
+            }
+            key.push(c);
+            chars.next();
+        }
+        if key.is_empty() {
+            break;
+        }
+        while chars.peek().is_some_and(|c| c.is_whitespace()) {
+            chars.next();
+        }
+        if chars.next() != Some('=') {
+            panic!("InvalidConnStr; missing \"=\" after \"{}\"", key);
+        }
+        while chars.peek().is_some_and(|c| c.is_whitespace()) {
+            chars.next();
+        }
+        let mut value = String::new();
+        let quoted = chars.peek() == Some(&'\'');
+        if quoted {
+            chars.next();
+        }
+        while let Some(c) = chars.next() {
+            match c {
+                '\\' => {
+                    if let Some(c) = chars.next() {
+                        value.push(c);
+                    }
+                }
+                '\'' if quoted => break,
+                c if c.is_whitespace() && !quoted => break,
+                c => value.push(c),
+            }
+        }
+        params.push((key, value));
+    }
+    params
+}
+
+fn quote(value: &str) -> String {
+    format!("'{}'", value.replace('\\', "\\\\").replace('\'', "\\'"))
+}
+
+// Returns connstr without `keys`, and the values of `keys` found in connstr.
+pub fn split_params(connstr: &str, keys: &[&str]) -> (String, HashMap<String, String>) {
+    let mut taken = HashMap::new();
+    if is_uri(connstr) {
+        let (base, query) = match connstr.find('?') {
+            Some(idx) => (&connstr[..idx], &connstr[idx + 1..]),
+            None => return (String::from(connstr), taken),
+        };
+        let mut rest = vec![];
+        for param in query.split('&').filter(|p| !p.is_empty()) {
+            let (key, value) = match param.find('=') {
+                Some(idx) => (&param[..idx], &param[idx + 1..]),
+                None => (param, ""),
+            };
+            if keys.contains(&key) {
+                taken.insert(String::from(key), percent_decode(value));
+            } else {
+                rest.push(param);
+            }
+        }
+        if rest.is_empty() {
+            return (String::from(base), taken);
+        }
+        return (format!("{}?{}", base, rest.join("&")), taken);
+    }
+
+    let mut rest = vec![];
+    for (key, value) in parse_keyvalue(connstr) {
+        if keys.contains(&key.as_str()) {
+            taken.insert(key, value);
+        } else {
+            rest.push(format!("{}={}", key, quote(value.as_str())));
+        }
+    }
+    (rest.join(" "), taken)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pairs(connstr: &str) -> Vec<(String, String)> {
+        parse_keyvalue(connstr)
+    }
+
+    fn pair(key: &str, value: &str) -> (String, String) {
+        (String::from(key), String::from(value))
+    }
+
+    #[test]
+    fn test_parse_keyvalue() {
+        assert_eq!(
+            pairs("host=mdw  port = 5432 dbname='my db'"),
+            vec![
+                pair("host", "mdw"),
+                pair("port", "5432"),
+                pair("dbname", "my db")
+            ]
+        );
+        assert_eq!(
+            pairs(r"password='it\'s a \\secret' user=gp\ admin"),
+            vec![
+                pair("password", r"it's a \secret"),
+                pair("user", "gp admin")
+            ]
+        );
+        assert_eq!(pairs("dbname=''"), vec![pair("dbname", "")]);
+        assert_eq!(pairs("   "), vec![]);
+    }
+
+    #[test]
+    #[should_panic(expected = "InvalidConnStr")]
+    fn test_parse_keyvalue_missing_eq() {
+        parse_keyvalue("host mdw");
+    }
+
+    #[test]
+    fn test_split_keyvalue() {
+        let (rest, taken) = split_params(
+            "host=mdw sslmode=require dbname='my db' sslrootcert='/etc/my certs/root.crt'",
+            &["sslmode", "sslrootcert"],
+        );
+        assert_eq!(rest, "host='mdw' dbname='my db'");
+        assert_eq!(taken["sslmode"], "require");
+        assert_eq!(taken["sslrootcert"], "/etc/my certs/root.crt");
+        // what is left is quoted back, so it parses to the same values.
+        let (rest, _) = split_params(r"password='it\'s \\ me' connect_timeout=5", &[]);
+        assert_eq!(
+            pairs(rest.as_str()),
+            pairs(r"password='it\'s \\ me' connect_timeout=5")
+        );
+    }
+
+    #[test]
+    fn test_split_uri() {
+        let (rest, taken) = split_params(
+            "postgresql://gpadmin@mdw:5432/db?sslmode=verify-full&application_name=x&sslcert=%2Fhome%2Fgp%2Fa%20b.crt",
+            &["sslmode", "sslcert"],
+        );
+        assert_eq!(rest, "postgresql://gpadmin@mdw:5432/db?application_name=x");
+        assert_eq!(taken["sslmode"], "verify-full");
+        assert_eq!(taken["sslcert"], "/home/gp/a b.crt");
+
+        let (rest, taken) = split_params("postgres://mdw/db?sslmode=require", &["sslmode"]);
+        assert_eq!(rest, "postgres://mdw/db");
+        assert_eq!(taken["sslmode"], "require");
+
+        let (rest, taken) = split_params("postgres://mdw/db", &["sslmode"]);
+        assert_eq!(rest, "postgres://mdw/db");
+        assert!(taken.is_empty());
+    }
+
+    #[test]
+    fn test_percent_decode() {
+        assert_eq!(percent_decode("a%20b"), "a b");
+        assert_eq!(percent_decode("%2F"), "/");
+        assert_eq!(percent_decode("root%2Ecrt%41"), "root.crtA");
+        // incomplete or invalid escapes are kept as they are.
+        assert_eq!(percent_decode("100%"), "100%");
+        assert_eq!(percent_decode("a%4"), "a%4");
+        assert_eq!(percent_decode("%zz"), "%zz");
+    }
+}
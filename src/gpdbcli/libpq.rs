@@ -0,0 +1,262 @@
+/*
+Copyright 2020 <盏一 w@hidva.com>
+Licensed under the Apache License, Version 2.0 (the "License");
+you may not use this file except in compliance with the License.
+You may obtain a copy of the License at
+
+http://www.apache.org/licenses/LICENSE-2.0
+
+Unless required by applicable law or agreed to in writing, software
+distributed under the License is distributed on an "AS IS" BASIS,
+WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+See the License for the specific language governing permissions and
+limitations under the License.
+*/
+// The files libpq reads besides the connection string: pg_service.conf and .pgpass.
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+fn home_file(name: &str) -> Option<PathBuf> {
+    let home = std::env::var("HOME").ok()?;
+    Some(Path::new(home.as_str()).join(name))
+}
+
+fn read_service_file(path: &Path, service: &str) -> Option<HashMap<String, String>> {
+    let content = std::fs::read_to_string(path).ok()?;
+    let mut params = HashMap::new();
+    let mut found = false;
+    let mut insection = false;
+    for line in content.lines() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        if line.starts_with('[') && line.ends_with(']') {
+            insection = &line[1..line.len() - 1] == service;
+            found |= insection;
+            continue;
+        }
+        if !insection {
+            continue;
+        }
+        if let Some(idx) = line.find('=') {
+            let key = line[..idx].trim();
+            let value = line[idx + 1..].trim();
+            params.insert(String::from(key), String::from(value));
+        }
+    }
+    if found {
+        Some(params)
+    } else {
+        None
+    }
+}
+
+// Parameters of `service`, from PGSERVICEFILE or ~/.pg_service.conf, then
+// $PGSYSCONFDIR/pg_service.conf. Like libpq, a service that cannot be found is an error.
+pub fn read_service(service: &str) -> HashMap<String, String> {
+    let mut paths = vec![];
+    match std::env::var("PGSERVICEFILE") {
+        Ok(path) => paths.push(PathBuf::from(path)),
+        Err(_) => paths.extend(home_file(".pg_service.conf")),
+    }
+    if let Ok(dir) = std::env::var("PGSYSCONFDIR") {
+        paths.push(Path::new(dir.as_str()).join("pg_service.conf"));
+    }
+    for path in paths.iter() {
+        if let Some(params) = read_service_file(path, service) {
+            return params;
+        }
+    }
+    panic!("UnknownService={}; searched {:?}", service, paths)
+}
+
+// Fields are separated by ':', "\:" and "\\" are a literal ':' and '\'.
+fn split_pgpass_line(line: &str) -> Vec<String> {
+    let mut fields = vec![String::new()];
+    let mut chars = line.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => {
+                if let Some(c) = chars.next() {
+                    fields.last_mut().unwrap().push(c);
+                }
+            }
+            ':' => fields.push(String::new()),
+            c => fields.last_mut().unwrap().push(c),
+        }
+    }
+    fields
+}
+
+#[cfg(unix)]
+fn is_private(path: &Path) -> bool {
+    use std::os::unix::fs::PermissionsExt;
+    match std::fs::metadata(path) {
+        Ok(meta) => meta.permissions().mode() & 0o077 == 0,
+        Err(_) => false,
+    }
+}
+
+#[cfg(not(unix))]
+fn is_private(_: &Path) -> bool {
+    true
+}
+
+// Look up the password in `passfile`, PGPASSFILE or ~/.pgpass. As libpq does, a socket
+// directory host matches "localhost", and a file readable by group or others is ignored.
+pub fn read_pgpass(
+    passfile: Option<&str>,
+    host: &str,
+    port: u16,
+    dbname: &str,
+    user: &str,
+) -> Option<String> {
+    let path = match passfile {
+        Some(path) => PathBuf::from(path),
+        None => match std::env::var("PGPASSFILE") {
+            Ok(path) => PathBuf::from(path),
+            Err(_) => home_file(".pgpass")?,
+        },
+    };
+    let content = std::fs::read_to_string(&path).ok()?;
+    if !is_private(&path) {
+        eprintln!(
+            "WARNING: password file {} has group or world access; permissions should be u=rw (0600) or less",
+            path.display()
+        );
+        return None;
+    }
+    let host = if host.starts_with('/') {
+        "localhost"
+    } else {
+        host
+    };
+    let port = port.to_string();
+    let want = [host, port.as_str(), dbname, user];
+    for line in content.lines() {
+        if line.starts_with('#') {
+            continue;
+        }
+        let fields = split_pgpass_line(line);
+        if fields.len() < 5 {
+            continue;
+        }
+        let matched = want
+            .iter()
+            .zip(fields.iter())
+            .all(|(want, field)| field == "*" || field == want);
+        if matched {
+            return Some(fields[4..].join(":"));
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A private password file with `content`, removed when dropped.
+    struct PassFile(PathBuf);
+
+    impl PassFile {
+        fn new(name: &str, content: &str) -> PassFile {
+            let path = std::env::temp_dir().join(format!(
+                "waitforgraph-{}-{}.pgpass",
+                std::process::id(),
+                name
+            ));
+            std::fs::write(&path, content).unwrap();
+            #[cfg(unix)]
+            {
+                use std::os::unix::fs::PermissionsExt;
+                let perms = std::fs::Permissions::from_mode(0o600);
+                std::fs::set_permissions(&path, perms).unwrap();
+            }
+            PassFile(path)
+        }
+
+        fn lookup(&self, host: &str, port: u16, dbname: &str, user: &str) -> Option<String> {
+            read_pgpass(self.0.to_str(), host, port, dbname, user)
+        }
+    }
+
+    impl Drop for PassFile {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_file(&self.0);
+        }
+    }
+
+    #[test]
+    fn test_split_pgpass_line() {
+        assert_eq!(
+            split_pgpass_line("mdw:5432:db:gpadmin:secret"),
+            vec!["mdw", "5432", "db", "gpadmin", "secret"]
+        );
+        assert_eq!(
+            split_pgpass_line(r"mdw:5432:d\:b:gp\\admin:a\:b\\c"),
+            vec!["mdw", "5432", "d:b", r"gp\admin", r"a:b\c"]
+        );
+        assert_eq!(split_pgpass_line("a:b:"), vec!["a", "b", ""]);
+    }
+
+    #[test]
+    fn test_read_pgpass() {
+        let file = PassFile::new(
+            "match",
+            "# host:port:db:user:password\n\
+            mdw:5432:db:gpadmin:first\n\
+            mdw:*:*:gpadmin:wildcard\n\
+            *:*:*:*:any\n",
+        );
+        assert_eq!(
+            file.lookup("mdw", 5432, "db", "gpadmin"),
+            Some("first".into())
+        );
+        assert_eq!(
+            file.lookup("mdw", 6000, "other", "gpadmin"),
+            Some("wildcard".into())
+        );
+        assert_eq!(file.lookup("sdw1", 6000, "db", "gp"), Some("any".into()));
+    }
+
+    #[test]
+    fn test_read_pgpass_escapes() {
+        let file = PassFile::new(
+            "escapes",
+            "mdw:5432:d\\:b:gp\\\\admin:pass\\:word\\\\\nmdw:5432:db:u:a:b\n",
+        );
+        assert_eq!(
+            file.lookup("mdw", 5432, "d:b", "gp\\admin"),
+            Some("pass:word\\".into())
+        );
+        assert_eq!(file.lookup("mdw", 5432, "d", "gp\\admin"), None);
+        // an unescaped ':' in the password is kept.
+        assert_eq!(file.lookup("mdw", 5432, "db", "u"), Some("a:b".into()));
+    }
+
+    #[test]
+    fn test_read_pgpass_socket_dir() {
+        let file = PassFile::new("socket", "localhost:5432:*:gpadmin:local\n");
+        assert_eq!(
+            file.lookup("/tmp", 5432, "db", "gpadmin"),
+            Some("local".into())
+        );
+        assert_eq!(
+            file.lookup("localhost", 5432, "db", "gpadmin"),
+            Some("local".into())
+        );
+        assert_eq!(file.lookup("mdw", 5432, "db", "gpadmin"), None);
+        assert_eq!(file.lookup("/tmp", 5433, "db", "gpadmin"), None);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_read_pgpass_not_private() {
+        use std::os::unix::fs::PermissionsExt;
+        let file = PassFile::new("public", "*:*:*:*:secret\n");
+        std::fs::set_permissions(&file.0, std::fs::Permissions::from_mode(0o644)).unwrap();
+        assert_eq!(file.lookup("mdw", 5432, "db", "gpadmin"), None);
+    }
+}
@@ -0,0 +1,133 @@
+/*
+Copyright 2020 <盏一 w@hidva.com>
+Licensed under the Apache License, Version 2.0 (the "License");
+you may not use this file except in compliance with the License.
+You may obtain a copy of the License at
+
+http://www.apache.org/licenses/LICENSE-2.0
+
+Unless required by applicable law or agreed to in writing, software
+distributed under the License is distributed on an "AS IS" BASIS,
+WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+See the License for the specific language governing permissions and
+limitations under the License.
+*/
+// TLS connector on top of native-tls, honoring sslmode, sslrootcert, sslcert and sslkey.
+use crate::gpdbcli::{GPDBError, SslConfig, SslMode};
+use native_tls::{Certificate, Identity};
+use postgres::tls::{ChannelBinding, MakeTlsConnect, TlsConnect, TlsStream};
+use postgres::Socket;
+use std::future::Future;
+use std::io;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use tokio::io::{AsyncRead, AsyncWrite};
+
+#[derive(Clone)]
+pub struct MakeNativeTlsConnect(tokio_tls::TlsConnector);
+
+pub struct NativeTlsConnect {
+    connector: tokio_tls::TlsConnector,
+    domain: String,
+}
+
+pub struct NativeTlsStream(tokio_tls::TlsStream<Socket>);
+
+fn read_file(path: &str) -> Result<Vec<u8>, GPDBError> {
+    std::fs::read(path).map_err(|e| GPDBError::Ssl(format!("{}: {}", path, e)))
+}
+
+fn ssl_err(err: native_tls::Error) -> GPDBError {
+    GPDBError::Ssl(err.to_string())
+}
+
+// Like libpq: require only encrypts, unless a root certificate is given, then it is verify-ca.
+pub fn connector(ssl: &SslConfig) -> Result<MakeNativeTlsConnect, GPDBError> {
+    let mut builder = native_tls::TlsConnector::builder();
+    if let Some(ref rootcert) = ssl.rootcert {
+        let pem = read_file(rootcert)?;
+        builder.add_root_certificate(Certificate::from_pem(&pem).map_err(ssl_err)?);
+        builder.disable_built_in_roots(true);
+    }
+    if let (Some(ref cert), Some(ref key)) = (&ssl.cert, &ssl.key) {
+        let cert = read_file(cert)?;
+        let key = read_file(key)?;
+        builder.identity(Identity::from_pkcs8(&cert, &key).map_err(ssl_err)?);
+    }
+    let verify_ca = match ssl.mode {
+        SslMode::VerifyCa | SslMode::VerifyFull => true,
+        SslMode::Require => ssl.rootcert.is_some(),
+        _ => false,
+    };
+    if !verify_ca {
+        builder.danger_accept_invalid_certs(true);
+    }
+    if ssl.mode != SslMode::VerifyFull {
+        builder.danger_accept_invalid_hostnames(true);
+    }
+    let connector = builder.build().map_err(ssl_err)?;
+    Ok(MakeNativeTlsConnect(tokio_tls::TlsConnector::from(
+        connector,
+    )))
+}
+
+impl MakeTlsConnect<Socket> for MakeNativeTlsConnect {
+    type Stream = NativeTlsStream;
+    type TlsConnect = NativeTlsConnect;
+    type Error = native_tls::Error;
+
+    fn make_tls_connect(&mut self, domain: &str) -> Result<NativeTlsConnect, native_tls::Error> {
+        Ok(NativeTlsConnect {
+            connector: self.0.clone(),
+            domain: String::from(domain),
+        })
+    }
+}
+
+impl TlsConnect<Socket> for NativeTlsConnect {
+    type Stream = NativeTlsStream;
+    type Error = native_tls::Error;
+    #[allow(clippy::type_complexity)]
+    type Future = Pin<Box<dyn Future<Output = Result<NativeTlsStream, native_tls::Error>> + Send>>;
+
+    fn connect(self, stream: Socket) -> Self::Future {
+        Box::pin(async move {
+            let stream = self.connector.connect(&self.domain, stream).await?;
+            Ok(NativeTlsStream(stream))
+        })
+    }
+}
+
+impl AsyncRead for NativeTlsStream {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.0).poll_read(cx, buf)
+    }
+}
+
+impl AsyncWrite for NativeTlsStream {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.0).poll_write(cx, buf)
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.0).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.0).poll_shutdown(cx)
+    }
+}
+
+impl TlsStream for NativeTlsStream {
+    fn channel_binding(&self) -> ChannelBinding {
+        ChannelBinding::none()
+    }
+}
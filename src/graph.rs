@@ -12,9 +12,9 @@ WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
 See the License for the specific language governing permissions and
 limitations under the License.
 */
-use crate::get_or_default;
 use crate::intern::*;
 use crate::lock::*;
+use crate::{fmt_age, get_or_default};
 use std::collections::{HashMap, HashSet};
 
 #[derive(Default)]
@@ -26,13 +26,56 @@ struct Vertex {
 type EdgeId = *const Edge;
 
 // waiter -> holder
-#[derive(PartialEq, Eq, Hash, Clone, Copy)]
+#[derive(PartialEq, Eq, Hash, Clone)]
 struct Edge {
     waiter: SessionId,
     holder: SessionId,
     wait: LockMode,
     hold: LockMode,
     obj: LockObjId,
+    // seconds since the waiter began to wait, if pg_locks.waitstart is known.
+    waited: Option<i64>,
+    // DistWait::solid, for edges from gp_dist_wait_status().
+    solid: Option<bool>,
+    // how many child relations the edges rolled up into this one were on, see
+    // LockInfo::fetch_rollup(). 0 if it is not rolled up.
+    children: usize,
+    // the modes the waiter already holds obj in, not empty if the edge is a lock upgrade. Taken
+    // before roll_up() replaces obj with the top-level table.
+    upgrading: Vec<LockMode>,
+}
+
+impl Edge {
+    // wait mode / granted mode, used as edge label by renderers that support it.
+    fn label(&self) -> String {
+        let mut label = format!("{} / {}", self.wait, self.hold);
+        if self.waited.is_some() {
+            label.push_str(", ");
+            label.push_str(&fmt_age(self.waited));
+        }
+        if self.solid == Some(false) {
+            label.push_str(", dotted");
+        }
+        label
+    }
+}
+
+// The node id of sessid for renderers whose ids must start with a letter (Mermaid, PlantUML).
+// Background workers and prepared transactions have negative ids, '-' is not allowed either.
+fn node_id(sessid: SessionId) -> String {
+    if sessid < 0 {
+        format!("sn{}", -sessid)
+    } else {
+        format!("s{}", sessid)
+    }
+}
+
+// A waiting lock nobody is seen to hold in a conflicting mode.
+pub struct UnexplainedWait {
+    pub sessid: SessionId,
+    pub lock: Lock,
+    // LockInfo::guess_unexplained().
+    pub guess: String,
 }
 
 #[derive(Default)]
@@ -40,6 +83,8 @@ pub struct WFGraph {
     data: LockInfo,
     edges: Internment<Edge>,
     sess_vert: HashMap<SessionId, Vertex>,
+    // their sessions are vertices too, maybe without any edge.
+    unexplained: Vec<UnexplainedWait>,
 }
 
 impl WFGraph {
@@ -55,33 +100,46 @@ impl WFGraph {
         &mut self,
         waiter: SessionId,
         holder: SessionId,
-        obj: LockObjId,
-        wait: LockMode,
+        lock: &Lock,
         hold: LockMode,
+        upgrading: &[LockMode],
     ) {
         let edgeid = self.get_edgeid(Edge {
             waiter,
             holder,
-            wait,
+            wait: lock.mode.clone(),
             hold,
-            obj,
+            obj: lock.objid,
+            waited: lock.wait_age,
+            solid: None,
+            children: 0,
+            upgrading: upgrading.to_vec(),
         });
         self.get_vert(waiter).edges_out.insert(edgeid);
         self.get_vert(holder).edges_in.insert(edgeid);
     }
 
     fn process_waiter(&mut self, waiter: SessionId, locks: &std::vec::Vec<Lock>, info: &LockInfo) {
-        for &lock in locks {
-            for &conflict_mode in lock.mode.conflict_modes() {
-                if let Some(holders) = info.get_holders(conflict_mode, lock.objid) {
-                    for &holder in holders {
-                        if waiter == holder {
-                            continue;
-                        }
-                        self.add_dependency(waiter, holder, lock.objid, lock.mode, conflict_mode);
-                    }
+        for lock in locks {
+            let slot = info.get_obj(lock.objid).is_slot();
+            let upgrading = info.held_modes(waiter, lock.objid);
+            let mut blocked = false;
+            for (hold, holder) in info.get_all_holders(lock.objid) {
+                if waiter != holder
+                    && (slot || lock.mode.conflicts_with(&hold, info.conflict_policy))
+                {
+                    self.add_dependency(waiter, holder, lock, hold, &upgrading);
+                    blocked = true;
                 }
             }
+            if !blocked {
+                self.get_vert(waiter);
+                self.unexplained.push(UnexplainedWait {
+                    sessid: waiter,
+                    lock: lock.clone(),
+                    guess: info.guess_unexplained(waiter, lock),
+                });
+            }
         }
     }
 
@@ -90,24 +148,631 @@ impl WFGraph {
         for (&waiter, locks) in info.waiter.iter() {
             ret.process_waiter(waiter, locks, &info);
         }
+        ret.unexplained
+            .sort_by_key(|wait| (wait.sessid, wait.lock.objid));
         ret.data = info;
+        ret.roll_up();
+        ret
+    }
+
+    // Edges straight from gp_dist_wait_status() instead of deriving them from the conflicts of
+    // the locks in `info`, which only describe them.
+    pub fn new_dist_wait(mut info: LockInfo, waits: &[DistWait]) -> WFGraph {
+        let mut edges = vec![];
+        for wait in waits.iter().filter(|wait| wait.waiter != wait.holder) {
+            let lock = info.dist_wait_lock(wait);
+            let hold = info.dist_wait_hold(wait, &lock);
+            edges.push(Edge {
+                waiter: wait.waiter,
+                holder: wait.holder,
+                wait: lock.mode.clone(),
+                hold,
+                obj: lock.objid,
+                waited: lock.wait_age,
+                solid: Some(wait.solid),
+                children: 0,
+                upgrading: info.held_modes(wait.waiter, lock.objid),
+            });
+        }
+        let mut ret = WFGraph::default();
+        for edge in edges {
+            let (waiter, holder) = (edge.waiter, edge.holder);
+            let edgeid = ret.get_edgeid(edge);
+            ret.get_vert(waiter).edges_out.insert(edgeid);
+            ret.get_vert(holder).edges_in.insert(edgeid);
+        }
+        ret.data = info;
+        ret.roll_up();
+        ret
+    }
+
+    // Merge the edges on the relations rolled up to the same top-level table, keeping the longest
+    // wait and every lock upgraded.
+    fn roll_up(&mut self) {
+        if !self.data.has_rollup() {
+            return;
+        }
+        let mut groups: HashMap<Edge, (HashSet<_>, Option<i64>, Vec<_>)> = HashMap::new();
+        for edge in self.edges.0.iter() {
+            let mut key = Edge::clone(edge);
+            key.obj = self.data.rolled_up(edge.obj).unwrap_or(edge.obj);
+            key.waited = None;
+            key.upgrading = vec![];
+            let (objs, waited, upgrading) = groups.entry(key).or_default();
+            objs.insert(edge.obj);
+            *waited = (*waited).max(edge.waited);
+            upgrading.extend(edge.upgrading.iter().cloned());
+        }
+        self.edges = Internment::default();
+        for vert in self.sess_vert.values_mut() {
+            vert.edges_in.clear();
+            vert.edges_out.clear();
+        }
+        for (mut edge, (objs, waited, mut upgrading)) in groups {
+            upgrading.sort_by_key(LockMode::to_string);
+            upgrading.dedup();
+            edge.upgrading = upgrading;
+            edge.waited = waited;
+            edge.children = objs.iter().filter(|&&obj| obj != edge.obj).count();
+            let (waiter, holder) = (edge.waiter, edge.holder);
+            let edgeid = self.get_edgeid(edge);
+            self.get_vert(waiter).edges_out.insert(edgeid);
+            self.get_vert(holder).edges_in.insert(edgeid);
+        }
+    }
+
+    // waiter -> holder pairs only in self and only in `other`, which comes from another source.
+    // Only the edges on the objects `visible` to the other source count.
+    #[allow(clippy::type_complexity)]
+    pub fn cross_check(
+        &self,
+        other: &HashSet<(SessionId, SessionId)>,
+        visible: impl Fn(&LockObj) -> bool,
+    ) -> (Vec<(SessionId, SessionId)>, Vec<(SessionId, SessionId)>) {
+        let pairs: HashSet<(SessionId, SessionId)> = self
+            .edges
+            .0
+            .iter()
+            .filter(|edge| visible(self.data.get_obj(edge.obj)))
+            .map(|edge| (edge.waiter, edge.holder))
+            .collect();
+        let mut only_self: Vec<_> = pairs.difference(other).copied().collect();
+        let mut only_other: Vec<_> = other.difference(&pairs).copied().collect();
+        only_self.sort_unstable();
+        only_other.sort_unstable();
+        (only_self, only_other)
+    }
+
+    fn get_edge(&self, edgeid: EdgeId) -> &Edge {
+        unsafe { &*edgeid }
+    }
+
+    // The locks the graph was built from.
+    pub fn lockinfo(&self) -> &LockInfo {
+        &self.data
+    }
+
+    pub fn sessions(&self) -> impl Iterator<Item = SessionId> + '_ {
+        self.sess_vert.keys().copied()
+    }
+
+    // sessions that sessid waits for.
+    pub fn blockers(&self, sessid: SessionId) -> Vec<SessionId> {
+        let mut ret: Vec<SessionId> = self
+            .sess_vert
+            .get(&sessid)
+            .map(|v| {
+                v.edges_out
+                    .iter()
+                    .map(|&e| self.get_edge(e).holder)
+                    .collect()
+            })
+            .unwrap_or_default();
+        ret.sort_unstable();
+        ret.dedup();
+        ret
+    }
+
+    // sessions that wait for sessid.
+    pub fn blocking(&self, sessid: SessionId) -> Vec<SessionId> {
+        let mut ret: Vec<SessionId> = self
+            .sess_vert
+            .get(&sessid)
+            .map(|v| {
+                v.edges_in
+                    .iter()
+                    .map(|&e| self.get_edge(e).waiter)
+                    .collect()
+            })
+            .unwrap_or_default();
+        ret.sort_unstable();
+        ret.dedup();
+        ret
+    }
+
+    pub fn desc_held(&self, sessid: SessionId) -> Vec<String> {
+        let locks = self.data.get_granted(sessid);
+        locks.iter().map(|lock| self.desc_lock(lock)).collect()
+    }
+
+    pub fn desc_waiting(&self, sessid: SessionId) -> Vec<String> {
+        self.data
+            .waiter
+            .get(&sessid)
+            .map(|locks| {
+                locks
+                    .iter()
+                    .map(|lock| {
+                        let desc = self.desc_wait(sessid, &lock.mode, lock.objid);
+                        match lock.wait_age {
+                            Some(_) => format!("{} for {}", desc, fmt_age(lock.wait_age)),
+                            None => desc,
+                        }
+                    })
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+
+    // Strongly connected components with more than one session, i.e. the deadlocks. Tarjan's algorithm.
+    pub fn cycles(&self) -> Vec<Vec<SessionId>> {
+        self.cycles_without(&HashSet::new())
+    }
+
+    // The cycles left once the sessions in `gone` are gone.
+    fn cycles_without(&self, gone: &HashSet<SessionId>) -> Vec<Vec<SessionId>> {
+        let blockers_of = |sessid: SessionId| -> Vec<SessionId> {
+            let mut blockers = self.blockers(sessid);
+            blockers.retain(|s| !gone.contains(s));
+            blockers
+        };
+        let mut sessions: Vec<SessionId> = self.sessions().filter(|s| !gone.contains(s)).collect();
+        sessions.sort_unstable();
+        let mut index: HashMap<SessionId, usize> = HashMap::new();
+        let mut lowlink: HashMap<SessionId, usize> = HashMap::new();
+        let mut onstack: HashSet<SessionId> = HashSet::new();
+        let mut stack: Vec<SessionId> = vec![];
+        let mut sccs = vec![];
+        for &root in sessions.iter() {
+            if index.contains_key(&root) {
+                continue;
+            }
+            // (session, its blockers, next blocker to visit)
+            let mut work = vec![(root, blockers_of(root), 0usize)];
+            index.insert(root, index.len());
+            lowlink.insert(root, index[&root]);
+            stack.push(root);
+            onstack.insert(root);
+            while let Some((v, blockers, next)) = work.last_mut() {
+                let v = *v;
+                if *next < blockers.len() {
+                    let w = blockers[*next];
+                    *next += 1;
+                    if !index.contains_key(&w) {
+                        index.insert(w, index.len());
+                        lowlink.insert(w, index[&w]);
+                        stack.push(w);
+                        onstack.insert(w);
+                        let wblockers = blockers_of(w);
+                        work.push((w, wblockers, 0));
+                    } else if onstack.contains(&w) {
+                        let low = lowlink[&v].min(index[&w]);
+                        lowlink.insert(v, low);
+                    }
+                    continue;
+                }
+                work.pop();
+                if let Some(&(parent, _, _)) = work.last() {
+                    let low = lowlink[&parent].min(lowlink[&v]);
+                    lowlink.insert(parent, low);
+                }
+                if lowlink[&v] == index[&v] {
+                    let mut scc = vec![];
+                    loop {
+                        let w = stack.pop().unwrap();
+                        onstack.remove(&w);
+                        scc.push(w);
+                        if w == v {
+                            break;
+                        }
+                    }
+                    if scc.len() > 1 {
+                        scc.sort_unstable();
+                        sccs.push(scc);
+                    }
+                }
+            }
+        }
+        sccs
+    }
+
+    // Deadlocks caused by lock upgrades: cycles in which every session waits to upgrade a lock,
+    // blocked by another session of the cycle holding the same object in a weaker mode. The classic
+    // is two sessions reading then locking the same table for writing. Row locks are not covered,
+    // who upgrades a row lock waits for the transaction of the other, which it never holds.
+    pub fn upgrade_cycles(&self) -> Vec<Vec<SessionId>> {
+        self.cycles()
+            .into_iter()
+            .filter(|cycle| {
+                cycle.iter().all(|&sessid| {
+                    self.sess_vert[&sessid]
+                        .edges_out
+                        .iter()
+                        .any(|&e| self.in_upgrade_cycle(self.get_edge(e), cycle))
+                })
+            })
+            .collect()
+    }
+
+    // Whether edge is one of the upgrades of cycle, see upgrade_cycles().
+    fn in_upgrade_cycle(&self, edge: &Edge, cycle: &[SessionId]) -> bool {
+        cycle.contains(&edge.waiter) && cycle.contains(&edge.holder) && !edge.upgrading.is_empty()
+    }
+
+    // Sessions to cancel so that every cycle is broken and every session in `unblock` can go on
+    // right away, which needs all its blockers gone. Finding the smallest such set is NP-hard, so
+    // greedily take the session with the most waits in and out of its cycle for its `weight` (what
+    // cancelling it costs, e.g. the work lost) until no cycle is left, then give back the costliest
+    // ones that turn out not to be needed. Sessions in `unblock` are never chosen.
+    pub fn cancel_set(
+        &self,
+        unblock: &[SessionId],
+        weight: impl Fn(SessionId) -> u64,
+    ) -> Vec<SessionId> {
+        let mut cancel: HashSet<SessionId> = unblock
+            .iter()
+            .flat_map(|&sessid| self.blockers(sessid))
+            .filter(|s| !unblock.contains(s))
+            .collect();
+        let mut chosen = vec![];
+        loop {
+            let cycles = self.cycles_without(&cancel);
+            if cycles.is_empty() {
+                break;
+            }
+            let before = cancel.len();
+            for cycle in cycles {
+                let inside = |sessions: Vec<SessionId>| {
+                    sessions.iter().filter(|s| cycle.contains(s)).count()
+                };
+                let score = |s: SessionId| {
+                    (inside(self.blockers(s)) * inside(self.blocking(s))) as f64
+                        / (weight(s) + 1) as f64
+                };
+                let best = cycle
+                    .iter()
+                    .copied()
+                    .filter(|s| !unblock.contains(s))
+                    .max_by(|&a, &b| {
+                        score(a)
+                            .partial_cmp(&score(b))
+                            .unwrap()
+                            .then_with(|| b.cmp(&a))
+                    });
+                if let Some(best) = best {
+                    cancel.insert(best);
+                    chosen.push(best);
+                }
+            }
+            // every session left in the cycles is in `unblock`.
+            if cancel.len() == before {
+                break;
+            }
+        }
+        chosen.sort_by_key(|&s| std::cmp::Reverse(weight(s)));
+        // sessions left in cycles, only the ones in `unblock` if any.
+        let stuck = |cancel: &HashSet<SessionId>| self.cycles_without(cancel).concat().len();
+        let left = stuck(&cancel);
+        for sessid in chosen {
+            cancel.remove(&sessid);
+            if stuck(&cancel) > left {
+                cancel.insert(sessid);
+            }
+        }
+        let mut cancel: Vec<SessionId> = cancel.into_iter().collect();
+        cancel.sort_unstable();
+        cancel
+    }
+
+    // Where to start when drawing the graph as blocking trees: the sessions blocking others without
+    // waiting themselves, plus one session of every cycle that is not reachable from them.
+    pub fn tree_roots(&self) -> Vec<SessionId> {
+        let mut roots: Vec<SessionId> = self
+            .sessions()
+            .filter(|&s| self.blockers(s).is_empty())
+            .collect();
+        roots.sort_unstable();
+        let mut reached: HashSet<SessionId> = HashSet::new();
+        let mut queue = roots.clone();
+        for cycle in self.cycles() {
+            while let Some(sessid) = queue.pop() {
+                if reached.insert(sessid) {
+                    queue.extend(self.blocking(sessid));
+                }
+            }
+            if !cycle.iter().any(|s| reached.contains(s)) {
+                roots.push(cycle[0]);
+                queue.push(cycle[0]);
+            }
+        }
+        roots
+    }
+
+    // The prepared transaction standing for sessid, if it is one.
+    pub fn prepared(&self, sessid: SessionId) -> Option<&PreparedXact> {
+        self.data.prepared.get(&sessid)
+    }
+
+    // What to call sessid in the output, prepared transactions go by their gid.
+    pub fn node_label(&self, sessid: SessionId) -> String {
+        match self.prepared(sessid) {
+            Some(prepared) => format!("prepared xact {}", prepared.gid),
+            None => sessid.to_string(),
+        }
+    }
+
+    fn desc_session(&self, sessid: SessionId) -> String {
+        match self.prepared(sessid) {
+            Some(prepared) => format!("prepared xact {}", prepared.gid),
+            None => format!("session {}", sessid),
+        }
+    }
+
+    pub fn unexplained(&self) -> &[UnexplainedWait] {
+        &self.unexplained
+    }
+
+    // Guesses why sessid waits with no visible blocker.
+    pub fn desc_unexplained(&self, sessid: SessionId) -> Vec<String> {
+        self.unexplained
+            .iter()
+            .filter(|wait| wait.sessid == sessid)
+            .map(|wait| format!("{}: {}", self.desc_lock(&wait.lock), wait.guess))
+            .collect()
+    }
+
+    // Nodes drawn highlighted: prepared transactions, and sessions waiting with no visible
+    // blocker.
+    pub fn node_color(&self, sessid: SessionId) -> Option<&'static str> {
+        if self.prepared(sessid).is_some() {
+            Some("orange")
+        } else if self.unexplained.iter().any(|wait| wait.sessid == sessid) {
+            Some("yellow")
+        } else {
+            None
+        }
+    }
+
+    // What the highlighted nodes are, for the comments of the renderers.
+    fn desc_nodes(&self) -> Vec<String> {
+        let mut sessions: Vec<SessionId> = self
+            .sessions()
+            .filter(|&s| self.prepared(s).is_some())
+            .collect();
+        sessions.sort_unstable();
+        let mut ret: Vec<String> = sessions
+            .into_iter()
+            .map(|s| format!("{};", self.data.prepared[&s]))
+            .collect();
+        for wait in self.unexplained.iter() {
+            ret.push(format!(
+                "session {} waits for {} with no visible blocker, {};",
+                wait.sessid,
+                self.desc_lock(&wait.lock),
+                wait.guess
+            ));
+        }
+        for cycle in self.upgrade_cycles() {
+            let mut objs: Vec<String> = self
+                .edges
+                .0
+                .iter()
+                .filter(|edge| self.in_upgrade_cycle(edge, &cycle))
+                .map(|edge| self.desc_obj(edge.obj))
+                .collect();
+            objs.sort_unstable();
+            objs.dedup();
+            ret.push(format!(
+                "deadlock by lock upgrade: sessions {} each wait to upgrade their lock on {}, \
+                blocked by the weaker lock another one of them holds, none can go on until one is \
+                cancelled; take the strongest mode needed up front instead, e.g. LOCK TABLE ... \
+                IN ... MODE or SELECT ... FOR UPDATE;",
+                cycle
+                    .iter()
+                    .map(SessionId::to_string)
+                    .collect::<Vec<_>>()
+                    .join(", "),
+                objs.join(", ")
+            ));
+        }
         ret
     }
 
-    fn get_obj(&self, objid: LockObjId) -> &LockObj {
-        self.data.get_obj(objid)
+    fn desc_obj(&self, objid: LockObjId) -> String {
+        self.data.desc_obj(objid)
+    }
+
+    fn desc_lock(&self, lock: &Lock) -> String {
+        format!("{} on {}", lock.mode, self.desc_obj(lock.objid))
+    }
+
+    // What sessid waits for, row locks are told by the row.
+    fn desc_wait(&self, sessid: SessionId, mode: &LockMode, objid: LockObjId) -> String {
+        let rowwait = match self.data.row_wait(sessid, objid) {
+            Some(rowwait) => rowwait,
+            None => return format!("{} on {}", mode, self.desc_obj(objid)),
+        };
+        let queued = match rowwait.queued_behind {
+            Some(holder) => format!(", queued behind session {}", holder),
+            None => String::new(),
+        };
+        format!(
+            "{} ({} on {}{})",
+            self.data.desc_row_wait(&rowwait),
+            mode,
+            self.desc_obj(objid),
+            queued
+        )
     }
 
     fn desc_edge(&self, edge: &Edge) -> String {
+        let waited = match edge.waited {
+            Some(_) => format!(" for {}", fmt_age(edge.waited)),
+            None => String::new(),
+        };
+        let children = match edge.children {
+            0 => String::new(),
+            1 => String::from(" (rolled up from 1 child relation)"),
+            n => format!(" (rolled up from {} child relations)", n),
+        };
+        let upgrading = if edge.upgrading.is_empty() {
+            String::new()
+        } else {
+            let held: Vec<String> = edge.upgrading.iter().map(LockMode::to_string).collect();
+            format!(", upgrading from {}", held.join(", "))
+        };
         format!(
-            "session {} waits for {} on {}; blocked by session {}(granted {});",
+            "session {} waits for {}{}{}{}; blocked by {}(granted {});",
             edge.waiter,
-            edge.wait,
-            self.get_obj(edge.obj),
-            edge.holder,
+            self.desc_wait(edge.waiter, &edge.wait, edge.obj),
+            children,
+            waited,
+            upgrading,
+            self.desc_session(edge.holder),
             edge.hold
         )
     }
 }
 
 pub mod dot;
+
+pub mod mermaid;
+
+pub mod plantuml;
+
+pub mod html;
+
+pub mod layout;
+
+pub mod svg;
+
+pub mod ascii;
+
+pub mod json;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A graph of waiter -> holder edges, all on the same made-up table lock.
+    fn graph(edges: &[(SessionId, SessionId)]) -> WFGraph {
+        let mut wfg = WFGraph::default();
+        for &(waiter, holder) in edges {
+            let edgeid = wfg.get_edgeid(Edge {
+                waiter,
+                holder,
+                wait: LockMode::AccessExclusiveLock,
+                hold: LockMode::AccessShareLock,
+                obj: std::ptr::null(),
+                waited: None,
+                solid: None,
+                children: 0,
+                upgrading: vec![],
+            });
+            wfg.get_vert(waiter).edges_out.insert(edgeid);
+            wfg.get_vert(holder).edges_in.insert(edgeid);
+        }
+        wfg
+    }
+
+    // cancel breaks every cycle, frees every waiter in unblock and spares them.
+    fn check_cancel(wfg: &WFGraph, unblock: &[SessionId], cancel: &[SessionId]) {
+        let gone: HashSet<SessionId> = cancel.iter().copied().collect();
+        assert_eq!(wfg.cycles_without(&gone), Vec::<Vec<SessionId>>::new());
+        for sessid in unblock {
+            assert!(!gone.contains(sessid), "{} is cancelled", sessid);
+            for blocker in wfg.blockers(*sessid) {
+                assert!(
+                    gone.contains(&blocker),
+                    "{} still waits for {}",
+                    sessid,
+                    blocker
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_cycles() {
+        let wfg = graph(&[(1, 2), (2, 3), (3, 1), (4, 1), (5, 6), (6, 5), (7, 7)]);
+        assert_eq!(wfg.cycles().len(), 2);
+        assert!(wfg.cycles().contains(&vec![1, 2, 3]));
+        assert!(wfg.cycles().contains(&vec![5, 6]));
+        let gone = [2].iter().copied().collect();
+        assert_eq!(wfg.cycles_without(&gone), vec![vec![5, 6]]);
+    }
+
+    #[test]
+    fn test_cancel_set_no_cycle() {
+        let wfg = graph(&[(1, 2), (2, 3)]);
+        assert_eq!(wfg.cancel_set(&[], |_| 1), Vec::<SessionId>::new());
+        let cancel = wfg.cancel_set(&[1], |_| 1);
+        assert_eq!(cancel, vec![2]);
+        check_cancel(&wfg, &[1], &cancel);
+    }
+
+    #[test]
+    fn test_cancel_set_overlapping_cycles() {
+        // 1 <-> 2 <-> 3, both cycles go through 2.
+        let wfg = graph(&[(1, 2), (2, 1), (2, 3), (3, 2)]);
+        let cancel = wfg.cancel_set(&[], |_| 1);
+        assert_eq!(cancel, vec![2]);
+        check_cancel(&wfg, &[], &cancel);
+        // 2 has too much to lose.
+        let cancel = wfg.cancel_set(&[], |s| if s == 2 { 100 } else { 1 });
+        assert_eq!(cancel, vec![1, 3]);
+        check_cancel(&wfg, &[], &cancel);
+
+        // 1 -> 2 -> 3 -> 1 and 3 -> 4 -> 1 share 1 and 3, 5 <-> 4 hangs off the second one.
+        let wfg = graph(&[(1, 2), (2, 3), (3, 1), (3, 4), (4, 1), (4, 5), (5, 4)]);
+        let cancel = wfg.cancel_set(&[], |_| 1);
+        check_cancel(&wfg, &[], &cancel);
+        assert!(cancel.len() <= 2, "{:?}", cancel);
+        let cancel = wfg.cancel_set(&[5], |_| 1);
+        check_cancel(&wfg, &[5], &cancel);
+    }
+
+    #[test]
+    fn test_cancel_set_unblock_in_cycle() {
+        // 1 -> 2 -> 3 -> 1, 4 waits for 1.
+        let wfg = graph(&[(1, 2), (2, 3), (3, 1), (4, 1)]);
+        let cancel = wfg.cancel_set(&[1], |_| 1);
+        assert_eq!(cancel, vec![2]);
+        check_cancel(&wfg, &[1], &cancel);
+        let cancel = wfg.cancel_set(&[4], |_| 1);
+        assert_eq!(cancel, vec![1]);
+        check_cancel(&wfg, &[4], &cancel);
+        // freeing 3 breaks the cycle already, nothing else is cancelled.
+        let cancel = wfg.cancel_set(&[3, 4], |_| 1);
+        assert_eq!(cancel, vec![1]);
+        check_cancel(&wfg, &[3, 4], &cancel);
+
+        // two cycles through 1, which must go on: both its blockers go.
+        let wfg = graph(&[(1, 2), (2, 1), (1, 3), (3, 4), (4, 1)]);
+        let cancel = wfg.cancel_set(&[1], |_| 1);
+        assert_eq!(cancel, vec![2, 3]);
+        check_cancel(&wfg, &[1], &cancel);
+    }
+
+    #[test]
+    fn test_cancel_set_unblock_each_other() {
+        // 1 and 2 wait for each other, neither can be freed without cancelling the other.
+        let wfg = graph(&[(1, 2), (2, 1), (3, 4), (4, 3)]);
+        let cancel = wfg.cancel_set(&[1, 2], |_| 1);
+        assert_eq!(cancel, vec![3]);
+        assert_eq!(
+            wfg.cycles_without(&cancel.iter().copied().collect()),
+            vec![vec![1, 2]]
+        );
+    }
+}
@@ -0,0 +1,94 @@
+/*
+Copyright 2020 <盏一 w@hidva.com>
+Licensed under the Apache License, Version 2.0 (the "License");
+you may not use this file except in compliance with the License.
+You may obtain a copy of the License at
+
+http://www.apache.org/licenses/LICENSE-2.0
+
+Unless required by applicable law or agreed to in writing, software
+distributed under the License is distributed on an "AS IS" BASIS,
+WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+See the License for the specific language governing permissions and
+limitations under the License.
+*/
+// Blocking trees drawn with box-drawing characters: every holder is followed by the sessions
+// waiting for it, together with the wait and granted lock modes.
+use crate::graph::*;
+use std::borrow::Borrow;
+use std::collections::HashSet;
+
+fn labels(wfg: &WFGraph, waiter: SessionId, holder: SessionId) -> String {
+    let mut labels: Vec<String> = wfg.sess_vert[&waiter]
+        .edges_out
+        .iter()
+        .map(|&e| wfg.get_edge(e))
+        .filter(|edge| edge.holder == holder)
+        .map(|edge| edge.label())
+        .collect();
+    labels.sort();
+    labels.dedup();
+    labels.join(", ")
+}
+
+// A session blocking several others is drawn once with its subtree, then as "(see above)", or
+// the output would grow exponentially with the pile-ups.
+fn render_tree(
+    wfg: &WFGraph,
+    sessid: SessionId,
+    prefix: &str,
+    path: &mut Vec<SessionId>,
+    visited: &mut HashSet<SessionId>,
+    out: &mut Vec<String>,
+) {
+    let children = wfg.blocking(sessid);
+    path.push(sessid);
+    for (i, &child) in children.iter().enumerate() {
+        let last = i + 1 == children.len();
+        let cyclic = path.contains(&child);
+        let repeated = !cyclic && !visited.insert(child);
+        out.push(format!(
+            "{}{}{} [{}]{}",
+            prefix,
+            if last { "└── " } else { "├── " },
+            wfg.node_label(child),
+            labels(wfg, child, sessid),
+            if cyclic {
+                " (cycle)"
+            } else if repeated {
+                " (see above)"
+            } else {
+                ""
+            }
+        ));
+        if !cyclic && !repeated {
+            let prefix = format!("{}{}", prefix, if last { "    " } else { "│   " });
+            render_tree(wfg, child, prefix.as_str(), path, visited, out);
+        }
+    }
+    path.pop();
+}
+
+pub fn render(wfg: &WFGraph) -> String {
+    let mut out = vec![String::from(
+        "WaitForGraph - Generated By hidva/waitforgraph",
+    )];
+
+    let mut path = vec![];
+    let mut visited = HashSet::new();
+    for root in wfg.tree_roots() {
+        out.push(String::new());
+        out.push(wfg.node_label(root));
+        visited.insert(root);
+        render_tree(wfg, root, "", &mut path, &mut visited, &mut out);
+    }
+
+    out.push(String::new());
+    for edgebox in wfg.edges.0.iter() {
+        let edge: &Edge = Borrow::borrow(edgebox);
+        out.push(wfg.desc_edge(edge));
+    }
+    out.extend(wfg.desc_nodes());
+
+    out.join("\n")
+}
@@ -12,20 +12,75 @@ WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
 See the License for the specific language governing permissions and
 limitations under the License.
 */
+use crate::fmt_age;
 use crate::graph::*;
 use std::borrow::Borrow;
 use std::collections::HashMap;
 use std::iter::Iterator;
 
+// The edge from node `waiter` to node `holder`, `upgrades` is wfg.upgrade_cycles().
+fn render_edge(
+    wfg: &WFGraph,
+    edge: &Edge,
+    upgrades: &[Vec<SessionId>],
+    waiter: String,
+    holder: String,
+) -> String {
+    let mut attrs = vec![];
+    if edge.waited.is_some() {
+        attrs.push(format!("label=\"{}\"", fmt_age(edge.waited)));
+    }
+    if edge.solid == Some(false) {
+        attrs.push(String::from("style=dashed"));
+    }
+    if upgrades
+        .iter()
+        .any(|cycle| wfg.in_upgrade_cycle(edge, cycle))
+    {
+        attrs.push(String::from("color=red"));
+    }
+    if attrs.is_empty() {
+        format!("{} -> {}", waiter, holder)
+    } else {
+        format!("{} -> {} [{}]", waiter, holder, attrs.join(","))
+    }
+}
+
 pub fn render(wfg: &WFGraph) -> String {
     let mut dot = vec![
         String::from("strict digraph G {"),
         String::from("label=\"WaitForGraph - Generated By hidva/waitforgraph\";"),
     ];
 
+    let upgrades = wfg.upgrade_cycles();
     for edgebox in wfg.edges.0.iter() {
         let edge: &Edge = Borrow::borrow(edgebox);
-        dot.push(format!("{} -> {}", edge.waiter, edge.holder));
+        dot.push(render_edge(
+            wfg,
+            edge,
+            &upgrades,
+            edge.waiter.to_string(),
+            edge.holder.to_string(),
+        ));
+    }
+
+    // prepared transactions hold locks without any session, nobody will release them but a
+    // COMMIT/ROLLBACK PREPARED. Sessions waiting with no visible blocker may have no edge at all.
+    for sessid in wfg.sessions() {
+        if let Some(color) = wfg.node_color(sessid) {
+            let shape = if wfg.prepared(sessid).is_some() {
+                "box"
+            } else {
+                "ellipse"
+            };
+            dot.push(format!(
+                "{} [label=\"{}\",shape={},style=filled,fillcolor={}]",
+                sessid,
+                wfg.node_label(sessid).replace('"', "\\\""),
+                shape,
+                color
+            ));
+        }
     }
 
     dot.push(String::from("}"));
@@ -34,6 +89,72 @@ pub fn render(wfg: &WFGraph) -> String {
     for edgebox in wfg.edges.0.iter() {
         dot.push(wfg.desc_edge(edgebox));
     }
+    dot.extend(wfg.desc_nodes());
+    dot.push(String::from("*/"));
+
+    dot.join("\n")
+}
+
+// One cluster per segment with the edges on its locks, so that it is clear which hosts to go to.
+// A session waiting on several segments shows up in each of them, as SESSID@SEGMENT.
+pub fn render_segments(wfg: &WFGraph) -> String {
+    let mut dot = vec![
+        String::from("strict digraph G {"),
+        String::from("label=\"WaitForGraph - Generated By hidva/waitforgraph\";"),
+    ];
+
+    let upgrades = wfg.upgrade_cycles();
+    let mut bysegment: HashMap<i64, Vec<&Edge>> = HashMap::new();
+    for edgebox in wfg.edges.0.iter() {
+        let edge: &Edge = Borrow::borrow(edgebox);
+        let segid = wfg.data.get_obj(edge.obj).segment().unwrap_or(-1);
+        bysegment.entry(segid).or_default().push(edge);
+    }
+    let mut segids: Vec<i64> = bysegment.keys().copied().collect();
+    segids.sort_unstable();
+    for segid in segids {
+        let node = |sessid: SessionId| format!("\"{}@{}\"", sessid, segid);
+        dot.push(format!("subgraph \"cluster_{}\" {{", segid));
+        dot.push(format!(
+            "label=\"{}\";",
+            wfg.data.desc_segment(segid).replace('"', "\\\"")
+        ));
+        let edges = &bysegment[&segid];
+        let mut sessions: Vec<SessionId> = edges
+            .iter()
+            .flat_map(|edge| vec![edge.waiter, edge.holder])
+            .collect();
+        sessions.sort_unstable();
+        sessions.dedup();
+        for sessid in sessions {
+            let mut attrs = vec![format!(
+                "label=\"{}\"",
+                wfg.node_label(sessid).replace('"', "\\\"")
+            )];
+            if let Some(color) = wfg.node_color(sessid) {
+                attrs.push(format!("style=filled,fillcolor={}", color));
+            }
+            dot.push(format!("{} [{}]", node(sessid), attrs.join(",")));
+        }
+        for edge in edges {
+            dot.push(render_edge(
+                wfg,
+                edge,
+                &upgrades,
+                node(edge.waiter),
+                node(edge.holder),
+            ));
+        }
+        dot.push(String::from("}"));
+    }
+
+    dot.push(String::from("}"));
+
+    dot.push(String::from("/*大吉大利~"));
+    for edgebox in wfg.edges.0.iter() {
+        dot.push(wfg.desc_edge(edgebox));
+    }
+    dot.extend(wfg.desc_nodes());
     dot.push(String::from("*/"));
 
     dot.join("\n")
@@ -56,5 +177,5 @@ pub fn render_tiny(
         }
     }
     dot.push(String::from("}"));
-    return dot.join("\n");
+    dot.join("\n")
 }
@@ -0,0 +1,167 @@
+/*
+Copyright 2020 <盏一 w@hidva.com>
+Licensed under the Apache License, Version 2.0 (the "License");
+you may not use this file except in compliance with the License.
+You may obtain a copy of the License at
+
+http://www.apache.org/licenses/LICENSE-2.0
+
+Unless required by applicable law or agreed to in writing, software
+distributed under the License is distributed on an "AS IS" BASIS,
+WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+See the License for the specific language governing permissions and
+limitations under the License.
+*/
+use crate::activity::{Activity, ActivityInfo};
+use crate::fmt_age;
+use crate::graph::json::{json_opt, json_str, json_strs};
+use crate::graph::*;
+use std::borrow::Borrow;
+
+// Everything the page needs is inlined so that the report is a single file without any CDN,
+// the graph itself is laid out by graph::svg.
+const TEMPLATE: &str = r##"<!DOCTYPE html>
+<html>
+<head>
+<meta charset="utf-8">
+<title>WaitForGraph - Generated By hidva/waitforgraph</title>
+<style>
+body { font-family: sans-serif; margin: 0; display: flex; flex-direction: column; height: 100vh; }
+header { padding: 8px; border-bottom: 1px solid #ccc; }
+main { flex: 1; display: flex; min-height: 0; }
+#graph { flex: 3; overflow: auto; }
+#details { flex: 1; overflow: auto; padding: 8px; border-left: 1px solid #ccc; font-size: 13px; }
+#edges { max-height: 30vh; overflow: auto; margin: 0; padding: 8px; border-top: 1px solid #ccc; font-size: 12px; }
+g.node { cursor: pointer; }
+g.node.selected ellipse { fill: #ffd54f; }
+g.node.matched ellipse { fill: #90caf9; }
+g.node text { pointer-events: none; }
+</style>
+</head>
+<body>
+<header>
+<b>WaitForGraph - Generated By hidva/waitforgraph</b>
+<input id="search" placeholder="session id or relation" size="40">
+</header>
+<main>
+<div id="graph">
+<!--WFG_SVG-->
+</div>
+<div id="details">Click a session to see what it is doing and its locks.</div>
+</main>
+<pre id="edges"></pre>
+<script>
+const WFG = /*WFG_DATA*/;
+
+function render() {
+  WFG.nodes.forEach(n => document.getElementById("node-" + n.id).addEventListener("click", () => select(n)));
+  document.getElementById("edges").textContent = WFG.edges.map(e => e.desc).join("\n");
+}
+
+function list(title, items) {
+  const ul = document.createElement("ul");
+  items.forEach(i => { const li = document.createElement("li"); li.textContent = i; ul.appendChild(li); });
+  const div = document.createElement("div");
+  div.innerHTML = "<b></b>";
+  div.firstChild.textContent = title + " (" + items.length + ")";
+  div.appendChild(ul);
+  return div;
+}
+
+function select(n) {
+  document.querySelectorAll("g.node.selected").forEach(c => c.classList.remove("selected"));
+  document.getElementById("node-" + n.id).classList.add("selected");
+  const details = document.getElementById("details");
+  details.innerHTML = "<h3></h3>";
+  details.firstChild.textContent = n.prepared ? n.prepared : "session " + n.id;
+  const a = n.activity;
+  if (a) {
+    details.appendChild(list("activity", [
+      "pid " + (a.pid === null ? "-" : a.pid), "user " + (a.user || "-"), "state " + (a.state || "-"),
+      "in transaction for " + a.xact_age, "query running for " + a.query_age]));
+    const query = document.createElement("pre");
+    query.style.whiteSpace = "pre-wrap";
+    query.textContent = a.query || "";
+    details.appendChild(query);
+  }
+  details.appendChild(list("waiting for", n.waiting));
+  details.appendChild(list("holding", n.held));
+  details.appendChild(list("no visible blocker", n.unexplained));
+  details.appendChild(list("blocked by", WFG.edges.filter(e => e.waiter === n.id).map(e => e.holder + ": " + e.label)));
+  details.appendChild(list("blocking", WFG.edges.filter(e => e.holder === n.id).map(e => e.waiter + ": " + e.label)));
+}
+
+function search(q) {
+  q = q.trim().toLowerCase();
+  WFG.nodes.forEach(n => {
+    const hit = q !== "" && (String(n.id).includes(q) || n.label.toLowerCase().includes(q) ||
+      n.held.concat(n.waiting).some(l => l.toLowerCase().includes(q)));
+    document.getElementById("node-" + n.id).classList.toggle("matched", hit);
+  });
+  const lines = WFG.edges.map(e => e.desc).filter(d => q === "" || d.toLowerCase().includes(q));
+  document.getElementById("edges").textContent = lines.join("\n");
+}
+
+render();
+document.getElementById("search").addEventListener("input", ev => search(ev.target.value));
+</script>
+</body>
+</html>
+"##;
+
+fn json_activity(activity: &Activity) -> String {
+    format!(
+        "{{\"pid\":{},\"user\":{},\"state\":{},\"query\":{},\"xact_age\":{},\"query_age\":{}}}",
+        json_opt(activity.pid),
+        json_opt(activity.usename.as_deref().map(json_str)),
+        json_opt(activity.state.as_deref().map(json_str)),
+        json_opt(activity.query.as_deref().map(json_str)),
+        json_str(fmt_age(activity.xact_age).as_str()),
+        json_str(fmt_age(activity.query_age).as_str())
+    )
+}
+
+// `activity` tells what the sessions are doing, sessions missing from it have no details.
+fn render_data(wfg: &WFGraph, activity: &ActivityInfo) -> String {
+    let mut nodes = vec![];
+    for &sessid in wfg.sess_vert.keys() {
+        let prepared = match wfg.prepared(sessid) {
+            Some(prepared) => json_str(prepared.to_string().as_str()),
+            None => String::from("null"),
+        };
+        nodes.push(format!(
+            "{{\"id\":{},\"label\":{},\"prepared\":{},\"activity\":{},\"held\":{},\"waiting\":{},\"unexplained\":{}}}",
+            sessid,
+            json_str(wfg.node_label(sessid).as_str()),
+            prepared,
+            json_opt(activity.sessions.get(&sessid).map(json_activity)),
+            json_strs(wfg.desc_held(sessid).into_iter()),
+            json_strs(wfg.desc_waiting(sessid).into_iter()),
+            json_strs(wfg.desc_unexplained(sessid).into_iter())
+        ));
+    }
+
+    let mut edges = vec![];
+    for edgebox in wfg.edges.0.iter() {
+        let edge: &Edge = Borrow::borrow(edgebox);
+        edges.push(format!(
+            "{{\"waiter\":{},\"holder\":{},\"label\":{},\"desc\":{}}}",
+            edge.waiter,
+            edge.holder,
+            json_str(edge.label().as_str()),
+            json_str(wfg.desc_edge(edge).as_str())
+        ));
+    }
+
+    format!(
+        "{{\"nodes\":[{}],\"edges\":[{}]}}",
+        nodes.join(","),
+        edges.join(",")
+    )
+}
+
+pub fn render(wfg: &WFGraph, activity: &ActivityInfo) -> String {
+    TEMPLATE
+        .replace("<!--WFG_SVG-->", svg::render_element(wfg).as_str())
+        .replace("/*WFG_DATA*/", render_data(wfg, activity).as_str())
+}
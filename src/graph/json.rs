@@ -0,0 +1,113 @@
+/*
+Copyright 2020 <盏一 w@hidva.com>
+Licensed under the Apache License, Version 2.0 (the "License");
+you may not use this file except in compliance with the License.
+You may obtain a copy of the License at
+
+http://www.apache.org/licenses/LICENSE-2.0
+
+Unless required by applicable law or agreed to in writing, software
+distributed under the License is distributed on an "AS IS" BASIS,
+WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+See the License for the specific language governing permissions and
+limitations under the License.
+*/
+use crate::gpdbcli::Segment;
+use crate::graph::*;
+use std::borrow::Borrow;
+
+pub fn json_str(s: &str) -> String {
+    let mut out = String::from("\"");
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            // keep "</script>" and friends out of the inline script.
+            '<' => out.push_str("\\u003c"),
+            '>' => out.push_str("\\u003e"),
+            '&' => out.push_str("\\u0026"),
+            c if (c as u32) < 0x20 => out.push_str(format!("\\u{:04x}", c as u32).as_str()),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+pub fn json_strs(v: impl Iterator<Item = String>) -> String {
+    let v: Vec<String> = v.map(|s| json_str(s.as_str())).collect();
+    format!("[{}]", v.join(","))
+}
+
+pub fn json_opt<T: ToString>(v: Option<T>) -> String {
+    v.map_or_else(|| String::from("null"), |v| v.to_string())
+}
+
+fn json_segment(segment: &Segment) -> String {
+    format!(
+        "{{\"content\":{},\"hostname\":{},\"port\":{},\"role\":{},\"preferred_role\":{},\"datadir\":{}}}",
+        segment.content,
+        json_str(segment.hostname.as_str()),
+        segment.port,
+        json_str(segment.role.as_str()),
+        json_str(segment.preferred_role.as_str()),
+        json_opt(segment.datadir.as_deref().map(json_str))
+    )
+}
+
+// For scripts: the segments, the sessions, and the edges with the segment their lock is on.
+pub fn render(wfg: &WFGraph) -> String {
+    let mut segments: Vec<&Segment> = wfg.data.segments.values().collect();
+    segments.sort_by_key(|segment| segment.content);
+    let segments: Vec<String> = segments.into_iter().map(json_segment).collect();
+
+    let mut sessions: Vec<SessionId> = wfg.sessions().collect();
+    sessions.sort_unstable();
+    let mut nodes = vec![];
+    for sessid in sessions {
+        nodes.push(format!(
+            "{{\"id\":{},\"label\":{},\"prepared\":{},\"held\":{},\"waiting\":{},\"unexplained\":{}}}",
+            sessid,
+            json_str(wfg.node_label(sessid).as_str()),
+            json_opt(wfg.prepared(sessid).map(|p| json_str(p.to_string().as_str()))),
+            json_strs(wfg.desc_held(sessid).into_iter()),
+            json_strs(wfg.desc_waiting(sessid).into_iter()),
+            json_strs(wfg.desc_unexplained(sessid).into_iter())
+        ));
+    }
+
+    let mut edges = vec![];
+    for edgebox in wfg.edges.0.iter() {
+        let edge: &Edge = Borrow::borrow(edgebox);
+        edges.push(format!(
+            "{{\"waiter\":{},\"holder\":{},\"wait\":{},\"hold\":{},\"object\":{},\"gp_segment_id\":{},\"waited\":{},\"desc\":{}}}",
+            edge.waiter,
+            edge.holder,
+            json_str(edge.wait.to_string().as_str()),
+            json_str(edge.hold.to_string().as_str()),
+            json_str(wfg.desc_obj(edge.obj).as_str()),
+            json_opt(wfg.data.get_obj(edge.obj).segment()),
+            json_opt(edge.waited),
+            json_str(wfg.desc_edge(edge).as_str())
+        ));
+    }
+
+    let cycles: Vec<String> = wfg
+        .cycles()
+        .iter()
+        .map(|cycle| {
+            let cycle: Vec<String> = cycle.iter().map(SessionId::to_string).collect();
+            format!("[{}]", cycle.join(","))
+        })
+        .collect();
+
+    format!(
+        "{{\"segments\":[{}],\"nodes\":[{}],\"edges\":[{}],\"cycles\":[{}],\"notes\":{}}}",
+        segments.join(","),
+        nodes.join(","),
+        edges.join(","),
+        cycles.join(","),
+        json_strs(wfg.desc_nodes().into_iter())
+    )
+}
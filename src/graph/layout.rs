@@ -0,0 +1,326 @@
+/*
+Copyright 2020 <盏一 w@hidva.com>
+Licensed under the Apache License, Version 2.0 (the "License");
+you may not use this file except in compliance with the License.
+You may obtain a copy of the License at
+
+http://www.apache.org/licenses/LICENSE-2.0
+
+Unless required by applicable law or agreed to in writing, software
+distributed under the License is distributed on an "AS IS" BASIS,
+WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+See the License for the specific language governing permissions and
+limitations under the License.
+*/
+// A small Sugiyama-style layered layout, so that we can draw the graph without Graphviz:
+// 1. break cycles by reversing DFS back edges,
+// 2. assign layers by longest path, waiters above holders,
+// 3. split edges spanning several layers with dummy vertices,
+// 4. reduce crossings with barycenter sweeps,
+// 5. assign coordinates.
+use crate::lock::SessionId;
+use std::collections::{HashMap, HashSet};
+
+// XSEP is between the centers of neighbouring vertices; vertices wider than XSEP - GAP keep GAP
+// between their borders instead.
+pub const XSEP: f64 = 110.0;
+const GAP: f64 = 38.0;
+pub const YSEP: f64 = 80.0;
+pub const MARGIN: f64 = 40.0;
+const SWEEPS: usize = 12;
+
+pub struct LayoutEdge {
+    pub waiter: SessionId,
+    pub holder: SessionId,
+    // from waiter to holder, including the bends at dummy vertices.
+    pub points: Vec<(f64, f64)>,
+}
+
+pub struct Layout {
+    pub width: f64,
+    pub height: f64,
+    pub nodes: Vec<(SessionId, f64, f64)>,
+    pub edges: Vec<LayoutEdge>,
+}
+
+struct Builder {
+    // None means a dummy vertex.
+    verts: Vec<Option<SessionId>>,
+    // what the vertex takes in its layer, see XSEP.
+    slot: Vec<f64>,
+    layer: Vec<usize>,
+    // per layer, vertices in drawing order.
+    layers: Vec<Vec<usize>>,
+    succ: Vec<Vec<usize>>,
+    pred: Vec<Vec<usize>>,
+}
+
+fn remove_cycles(n: usize, edges: &[(usize, usize)]) -> HashSet<(usize, usize)> {
+    let mut out: Vec<Vec<usize>> = vec![vec![]; n];
+    for &(from, to) in edges {
+        out[from].push(to);
+    }
+    // 0: unvisited, 1: on stack, 2: done.
+    let mut state = vec![0u8; n];
+    let mut reversed = HashSet::new();
+    for root in 0..n {
+        if state[root] != 0 {
+            continue;
+        }
+        let mut stack = vec![(root, 0usize)];
+        state[root] = 1;
+        while let Some(&mut (v, ref mut next)) = stack.last_mut() {
+            if *next < out[v].len() {
+                let w = out[v][*next];
+                *next += 1;
+                match state[w] {
+                    0 => {
+                        state[w] = 1;
+                        stack.push((w, 0));
+                    }
+                    1 => {
+                        reversed.insert((v, w));
+                    }
+                    _ => {}
+                }
+            } else {
+                state[v] = 2;
+                stack.pop();
+            }
+        }
+    }
+    reversed
+}
+
+fn assign_layers(n: usize, dag: &[(usize, usize)]) -> Vec<usize> {
+    let mut indeg = vec![0usize; n];
+    let mut out: Vec<Vec<usize>> = vec![vec![]; n];
+    for &(from, to) in dag {
+        out[from].push(to);
+        indeg[to] += 1;
+    }
+    let mut layer = vec![0usize; n];
+    let mut queue: Vec<usize> = (0..n).filter(|&v| indeg[v] == 0).collect();
+    while let Some(v) = queue.pop() {
+        for &w in out[v].iter() {
+            layer[w] = layer[w].max(layer[v] + 1);
+            indeg[w] -= 1;
+            if indeg[w] == 0 {
+                queue.push(w);
+            }
+        }
+    }
+    layer
+}
+
+impl Builder {
+    fn add_vert(&mut self, sess: Option<SessionId>, layer: usize, width: f64) -> usize {
+        let v = self.verts.len();
+        self.verts.push(sess);
+        self.slot.push((width + GAP).max(XSEP));
+        self.layer.push(layer);
+        self.succ.push(vec![]);
+        self.pred.push(vec![]);
+        if self.layers.len() <= layer {
+            self.layers.resize(layer + 1, vec![]);
+        }
+        self.layers[layer].push(v);
+        v
+    }
+
+    fn add_edge(&mut self, from: usize, to: usize) {
+        self.succ[from].push(to);
+        self.pred[to].push(from);
+    }
+
+    // returns the chain of vertices from `from` to `to`.
+    fn add_long_edge(&mut self, from: usize, to: usize) -> Vec<usize> {
+        let mut chain = vec![from];
+        let mut prev = from;
+        for layer in self.layer[from] + 1..self.layer[to] {
+            let dummy = self.add_vert(None, layer, 0.0);
+            self.add_edge(prev, dummy);
+            chain.push(dummy);
+            prev = dummy;
+        }
+        self.add_edge(prev, to);
+        chain.push(to);
+        chain
+    }
+
+    fn positions(&self) -> Vec<usize> {
+        let mut pos = vec![0; self.verts.len()];
+        for layer in self.layers.iter() {
+            for (i, &v) in layer.iter().enumerate() {
+                pos[v] = i;
+            }
+        }
+        pos
+    }
+
+    fn crossings(&self) -> usize {
+        let pos = self.positions();
+        let mut total = 0;
+        for layer in self.layers.iter() {
+            let mut segs = vec![];
+            for &v in layer.iter() {
+                for &w in self.succ[v].iter() {
+                    segs.push((pos[v], pos[w]));
+                }
+            }
+            for i in 0..segs.len() {
+                for j in i + 1..segs.len() {
+                    let (a, b) = (segs[i], segs[j]);
+                    if (a.0 < b.0 && a.1 > b.1) || (a.0 > b.0 && a.1 < b.1) {
+                        total += 1;
+                    }
+                }
+            }
+        }
+        total
+    }
+
+    fn sweep(&mut self, down: bool) {
+        let pos = self.positions();
+        let order: Vec<usize> = if down {
+            (1..self.layers.len()).collect()
+        } else {
+            (0..self.layers.len().saturating_sub(1)).rev().collect()
+        };
+        for l in order {
+            let mut keyed: Vec<(f64, usize)> = self.layers[l]
+                .iter()
+                .map(|&v| {
+                    let adj = if down { &self.pred[v] } else { &self.succ[v] };
+                    let key = if adj.is_empty() {
+                        pos[v] as f64
+                    } else {
+                        adj.iter().map(|&w| pos[w] as f64).sum::<f64>() / adj.len() as f64
+                    };
+                    (key, v)
+                })
+                .collect();
+            keyed.sort_by(|a, b| a.0.partial_cmp(&b.0).unwrap());
+            self.layers[l] = keyed.into_iter().map(|(_, v)| v).collect();
+        }
+    }
+
+    fn minimize_crossings(&mut self) {
+        let mut best = self.layers.clone();
+        let mut best_crossings = self.crossings();
+        for i in 0..SWEEPS {
+            if best_crossings == 0 {
+                break;
+            }
+            self.sweep(i % 2 == 0);
+            let crossings = self.crossings();
+            if crossings < best_crossings {
+                best_crossings = crossings;
+                best = self.layers.clone();
+            }
+        }
+        self.layers = best;
+    }
+
+    fn coordinates(&self) -> (Vec<(f64, f64)>, f64, f64) {
+        let span = |layer: &Vec<usize>| layer.iter().map(|&v| self.slot[v]).sum::<f64>();
+        let widest = self.layers.iter().map(span).fold(0.0, f64::max);
+        let mut coord = vec![(0.0, 0.0); self.verts.len()];
+        for (l, layer) in self.layers.iter().enumerate() {
+            // the first vertex of the widest layer is centered at MARGIN if it is not wide.
+            let mut x = MARGIN - XSEP / 2.0 + (widest - span(layer)) / 2.0;
+            for &v in layer.iter() {
+                coord[v] = (x + self.slot[v] / 2.0, MARGIN + l as f64 * YSEP);
+                x += self.slot[v];
+            }
+        }
+        let width = MARGIN * 2.0 + (widest - XSEP).max(0.0);
+        let height = MARGIN * 2.0 + self.layers.len().saturating_sub(1) as f64 * YSEP;
+        (coord, width, height)
+    }
+}
+
+// edges are waiter -> holder; duplicated edges are drawn once. `width` is how wide the node of a
+// session is drawn.
+pub fn layout(
+    sessions: impl Iterator<Item = SessionId>,
+    edges: impl Iterator<Item = (SessionId, SessionId)>,
+    width: impl Fn(SessionId) -> f64,
+) -> Layout {
+    let mut sessions: Vec<SessionId> = sessions.collect();
+    sessions.sort_unstable();
+    sessions.dedup();
+    let index: HashMap<SessionId, usize> =
+        sessions.iter().enumerate().map(|(i, &s)| (s, i)).collect();
+    let mut edges: Vec<(usize, usize)> = edges
+        .filter(|(waiter, holder)| waiter != holder)
+        .map(|(waiter, holder)| (index[&waiter], index[&holder]))
+        .collect();
+    edges.sort_unstable();
+    edges.dedup();
+
+    let reversed = remove_cycles(sessions.len(), &edges);
+    let dag: Vec<(usize, usize)> = edges
+        .iter()
+        .map(|&(from, to)| {
+            if reversed.contains(&(from, to)) {
+                (to, from)
+            } else {
+                (from, to)
+            }
+        })
+        .collect();
+    let layer = assign_layers(sessions.len(), &dag);
+
+    let mut builder = Builder {
+        verts: vec![],
+        slot: vec![],
+        layer: vec![],
+        layers: vec![],
+        succ: vec![],
+        pred: vec![],
+    };
+    for (i, &sess) in sessions.iter().enumerate() {
+        builder.add_vert(Some(sess), layer[i], width(sess));
+    }
+    // a -> b and b -> a may both exist, keep one chain per direction pair.
+    let mut chains: HashMap<(usize, usize), Vec<usize>> = HashMap::new();
+    for &(from, to) in dag.iter() {
+        chains
+            .entry((from, to))
+            .or_insert_with(|| builder.add_long_edge(from, to));
+    }
+    builder.minimize_crossings();
+    let (coord, width, height) = builder.coordinates();
+
+    let nodes = sessions
+        .iter()
+        .enumerate()
+        .map(|(i, &sess)| (sess, coord[i].0, coord[i].1))
+        .collect();
+    let edges = edges
+        .iter()
+        .map(|&(from, to)| {
+            let points = if reversed.contains(&(from, to)) {
+                chains[&(to, from)]
+                    .iter()
+                    .rev()
+                    .map(|&v| coord[v])
+                    .collect()
+            } else {
+                chains[&(from, to)].iter().map(|&v| coord[v]).collect()
+            };
+            LayoutEdge {
+                waiter: sessions[from],
+                holder: sessions[to],
+                points,
+            }
+        })
+        .collect();
+    Layout {
+        width,
+        height,
+        nodes,
+        edges,
+    }
+}
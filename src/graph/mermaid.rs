@@ -0,0 +1,52 @@
+/*
+Copyright 2020 <盏一 w@hidva.com>
+Licensed under the Apache License, Version 2.0 (the "License");
+you may not use this file except in compliance with the License.
+You may obtain a copy of the License at
+
+http://www.apache.org/licenses/LICENSE-2.0
+
+Unless required by applicable law or agreed to in writing, software
+distributed under the License is distributed on an "AS IS" BASIS,
+WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+See the License for the specific language governing permissions and
+limitations under the License.
+*/
+use crate::graph::*;
+use std::borrow::Borrow;
+
+pub fn render(wfg: &WFGraph) -> String {
+    let mut mmd = vec![
+        String::from("---"),
+        String::from("title: WaitForGraph - Generated By hidva/waitforgraph"),
+        String::from("---"),
+        String::from("graph LR"),
+    ];
+
+    for &sessid in wfg.sess_vert.keys() {
+        let label = wfg.node_label(sessid).replace('"', "#quot;");
+        mmd.push(format!("    {}[\"{}\"]", node_id(sessid), label));
+        if let Some(color) = wfg.node_color(sessid) {
+            mmd.push(format!("    style {} fill:{}", node_id(sessid), color));
+        }
+    }
+
+    for edgebox in wfg.edges.0.iter() {
+        let edge: &Edge = Borrow::borrow(edgebox);
+        mmd.push(format!(
+            "    {} -->|\"{}\"| {}",
+            node_id(edge.waiter),
+            edge.label(),
+            node_id(edge.holder)
+        ));
+    }
+
+    for edgebox in wfg.edges.0.iter() {
+        mmd.push(format!("%% {}", wfg.desc_edge(edgebox)));
+    }
+    for desc in wfg.desc_nodes() {
+        mmd.push(format!("%% {}", desc));
+    }
+
+    mmd.join("\n")
+}
@@ -0,0 +1,57 @@
+/*
+Copyright 2020 <盏一 w@hidva.com>
+Licensed under the Apache License, Version 2.0 (the "License");
+you may not use this file except in compliance with the License.
+You may obtain a copy of the License at
+
+http://www.apache.org/licenses/LICENSE-2.0
+
+Unless required by applicable law or agreed to in writing, software
+distributed under the License is distributed on an "AS IS" BASIS,
+WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+See the License for the specific language governing permissions and
+limitations under the License.
+*/
+use crate::graph::*;
+use std::borrow::Borrow;
+
+pub fn render(wfg: &WFGraph) -> String {
+    let mut puml = vec![
+        String::from("@startuml"),
+        String::from("title WaitForGraph - Generated By hidva/waitforgraph"),
+    ];
+
+    for &sessid in wfg.sess_vert.keys() {
+        let label = wfg.node_label(sessid).replace('"', "'");
+        if let Some(color) = wfg.node_color(sessid) {
+            puml.push(format!(
+                "agent \"{}\" as {} #{}",
+                label,
+                node_id(sessid),
+                color
+            ));
+        } else {
+            puml.push(format!("agent \"{}\" as {}", label, node_id(sessid)));
+        }
+    }
+
+    for edgebox in wfg.edges.0.iter() {
+        let edge: &Edge = Borrow::borrow(edgebox);
+        puml.push(format!(
+            "{} --> {} : {}",
+            node_id(edge.waiter),
+            node_id(edge.holder),
+            edge.label()
+        ));
+    }
+
+    puml.push(String::from("/'"));
+    for edgebox in wfg.edges.0.iter() {
+        puml.push(wfg.desc_edge(edgebox));
+    }
+    puml.extend(wfg.desc_nodes());
+    puml.push(String::from("'/"));
+
+    puml.push(String::from("@enduml"));
+    puml.join("\n")
+}
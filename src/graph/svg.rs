@@ -0,0 +1,145 @@
+/*
+Copyright 2020 <盏一 w@hidva.com>
+Licensed under the Apache License, Version 2.0 (the "License");
+you may not use this file except in compliance with the License.
+You may obtain a copy of the License at
+
+http://www.apache.org/licenses/LICENSE-2.0
+
+Unless required by applicable law or agreed to in writing, software
+distributed under the License is distributed on an "AS IS" BASIS,
+WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+See the License for the specific language governing permissions and
+limitations under the License.
+*/
+use crate::graph::layout::{self, Layout};
+use crate::graph::*;
+use std::borrow::Borrow;
+use std::collections::HashMap;
+
+const NODE_RX: f64 = 36.0;
+const NODE_RY: f64 = 16.0;
+// roughly the average width of a character at font-size 12.
+const CHAR_WIDTH: f64 = 7.0;
+// wide enough for the title line.
+const MIN_WIDTH: f64 = 360.0;
+
+pub fn escape(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+// The horizontal radius of the node of sessid, wide enough for its label.
+fn node_rx(wfg: &WFGraph, sessid: SessionId) -> f64 {
+    let chars = wfg.node_label(sessid).chars().count() as f64;
+    (chars * CHAR_WIDTH / 2.0 + 12.0).max(NODE_RX)
+}
+
+// move `to` towards `from` until it lies on the border of the ellipse centered at `to`.
+fn clip(from: (f64, f64), to: (f64, f64), rx: f64) -> (f64, f64) {
+    let (dx, dy) = (to.0 - from.0, to.1 - from.1);
+    if dx == 0.0 && dy == 0.0 {
+        return to;
+    }
+    let t = 1.0 / ((dx / rx).powi(2) + (dy / NODE_RY).powi(2)).sqrt();
+    (to.0 - dx * t, to.1 - dy * t)
+}
+
+fn layout_graph(wfg: &WFGraph) -> Layout {
+    layout::layout(
+        wfg.sess_vert.keys().copied(),
+        wfg.edges.0.iter().map(|edge| (edge.waiter, edge.holder)),
+        |sessid| node_rx(wfg, sessid) * 2.0,
+    )
+}
+
+// The <svg> element alone, nodes are `<g id="node-SESSID">` so that it can be scripted when embedded.
+pub fn render_element(wfg: &WFGraph) -> String {
+    let layout = layout_graph(wfg);
+    let width = layout.width.max(MIN_WIDTH);
+
+    // every (waiter, holder) pair is drawn once, titled with all its lock edges.
+    let mut titles: HashMap<(SessionId, SessionId), Vec<String>> = HashMap::new();
+    for edgebox in wfg.edges.0.iter() {
+        let edge: &Edge = Borrow::borrow(edgebox);
+        titles
+            .entry((edge.waiter, edge.holder))
+            .or_default()
+            .push(wfg.desc_edge(edge));
+    }
+
+    let mut svg = vec![
+        format!(
+            "<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"{0}\" height=\"{1}\" viewBox=\"0 0 {0} {1}\" font-family=\"sans-serif\" font-size=\"12\">",
+            width,
+            layout.height + layout::MARGIN
+        ),
+        String::from("<defs><marker id=\"arrow\" viewBox=\"0 0 10 10\" refX=\"10\" refY=\"5\" markerWidth=\"8\" markerHeight=\"8\" orient=\"auto\"><path d=\"M0,0L10,5L0,10z\" fill=\"#555\"/></marker></defs>"),
+    ];
+
+    for edge in layout.edges.iter() {
+        let mut points = edge.points.clone();
+        let n = points.len();
+        points[0] = clip(points[1], points[0], node_rx(wfg, edge.waiter));
+        points[n - 1] = clip(points[n - 2], points[n - 1], node_rx(wfg, edge.holder));
+        let points: Vec<String> = points
+            .iter()
+            .map(|(x, y)| format!("{:.1},{:.1}", x, y))
+            .collect();
+        let title = titles
+            .get(&(edge.waiter, edge.holder))
+            .map(|v| v.join("\n"))
+            .unwrap_or_default();
+        svg.push(format!(
+            "<polyline class=\"edge\" data-waiter=\"{}\" data-holder=\"{}\" points=\"{}\" fill=\"none\" stroke=\"#555\" marker-end=\"url(#arrow)\"><title>{}</title></polyline>",
+            edge.waiter,
+            edge.holder,
+            points.join(" "),
+            escape(title.as_str())
+        ));
+    }
+
+    for &(sessid, x, y) in layout.nodes.iter() {
+        let fill = wfg.node_color(sessid).unwrap_or("#fff");
+        svg.push(format!(
+            "<g class=\"node\" id=\"node-{0}\"><ellipse cx=\"{1:.1}\" cy=\"{2:.1}\" rx=\"{3}\" ry=\"{4}\" fill=\"{5}\" stroke=\"#000\"/><text x=\"{1:.1}\" y=\"{6:.1}\" text-anchor=\"middle\">{7}</text></g>",
+            sessid,
+            x,
+            y,
+            node_rx(wfg, sessid),
+            NODE_RY,
+            fill,
+            y + 4.0,
+            escape(wfg.node_label(sessid).as_str())
+        ));
+    }
+
+    svg.push(format!(
+        "<text x=\"{:.1}\" y=\"{:.1}\" text-anchor=\"middle\">WaitForGraph - Generated By hidva/waitforgraph</text>",
+        width / 2.0,
+        layout.height + layout::MARGIN / 2.0
+    ));
+    svg.push(String::from("</svg>"));
+    svg.join("\n")
+}
+
+pub fn render(wfg: &WFGraph) -> String {
+    let mut svg = vec![
+        String::from("<?xml version=\"1.0\" encoding=\"UTF-8\" standalone=\"no\"?>"),
+        render_element(wfg),
+    ];
+
+    // "--" is not allowed inside XML comments.
+    svg.push(String::from("<!--大吉大利~"));
+    for edgebox in wfg.edges.0.iter() {
+        svg.push(wfg.desc_edge(edgebox).replace("--", "- -"));
+    }
+    for desc in wfg.desc_nodes() {
+        svg.push(desc.replace("--", "- -"));
+    }
+    svg.push(String::from("-->"));
+
+    svg.join("\n")
+}
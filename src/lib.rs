@@ -42,6 +42,16 @@ where
     }
 }
 
+// Seconds to something like 1h2m, as shown next to sessions and waits.
+pub fn fmt_age(age: Option<i64>) -> String {
+    match age {
+        Some(age) if age >= 3600 => format!("{}h{}m", age / 3600, age % 3600 / 60),
+        Some(age) if age >= 60 => format!("{}m{}s", age / 60, age % 60),
+        Some(age) => format!("{}s", age),
+        None => String::from("-"),
+    }
+}
+
 mod intern;
 
 pub mod lock;
@@ -49,3 +59,11 @@ pub mod lock;
 pub mod gpdbcli;
 
 pub mod graph;
+
+pub mod activity;
+
+pub mod rwgraph;
+
+pub mod orphan;
+
+pub mod cancel;
@@ -17,6 +17,9 @@ use crate::intern::*;
 use crate::{get_or_default, Void};
 use postgres::{SimpleQueryMessage, SimpleQueryRow};
 use std::collections::{HashMap, HashSet};
+use std::sync::mpsc;
+use std::thread;
+use std::time::{Duration, Instant};
 use std::{fmt, str};
 
 #[derive(PartialEq, Eq, Hash, Default, Clone)]
@@ -34,8 +37,9 @@ pub struct LockObj {
     objsubid: Option<i64>,
 }
 
-impl fmt::Display for LockObj {
-    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+impl LockObj {
+    // All the fields, for lock types we do not know.
+    fn raw(&self) -> String {
         let mut v: Vec<String> = vec![];
         macro_rules! desc {
             ($field:ident) => {
@@ -57,12 +61,152 @@ impl fmt::Display for LockObj {
         desc!(objid);
         desc!(objsubid);
 
-        write!(f, "{}", v.join(","))
+        v.join(",")
+    }
+
+    fn or_unknown<T: fmt::Display>(val: Option<T>) -> String {
+        val.map_or_else(|| String::from("?"), |v| v.to_string())
+    }
+
+    // pg_advisory_lock(bigint) takes objsubid 1 with the key split into classid and objid,
+    // pg_advisory_lock(int, int) takes objsubid 2 with the keys in classid and objid.
+    fn desc_advisory(&self) -> String {
+        match (self.classid, self.objid, self.objsubid) {
+            (Some(hi), Some(lo), Some(1)) => {
+                let key = ((hi as u64) << 32 | (lo as u64 & 0xffff_ffff)) as i64;
+                format!("advisory lock key {} (int8)", key)
+            }
+            (Some(key1), Some(key2), Some(2)) => {
+                format!(
+                    "advisory lock key ({}, {}) (int4, int4)",
+                    key1 as i32, key2 as i32
+                )
+            }
+            _ => format!("advisory lock {}", self.raw()),
+        }
+    }
+
+    // `name` is what LockInfo knows about the object: the schema-qualified relation name of
+    // relation, extend, page and tuple locks, pg_describe_object() of object locks and the queue
+    // name of resource queue locks. `segment` is where gp_segment_id is, if known.
+    pub fn describe(&self, name: Option<&str>, segment: Option<&Segment>) -> String {
+        let relation = || self.desc_relation(name);
+        let desc = match self.locktype.as_deref() {
+            Some("relation") => relation(),
+            Some("extend") => format!("extension of {}", relation()),
+            Some("page") => format!("page {} of {}", LockObj::or_unknown(self.page), relation()),
+            Some("tuple") => format!(
+                "tuple ({},{}) of {}",
+                LockObj::or_unknown(self.page),
+                LockObj::or_unknown(self.tuple),
+                relation()
+            ),
+            Some("transactionid") => {
+                format!("transaction {}", LockObj::or_unknown(self.transactionid))
+            }
+            Some("virtualxid") => format!(
+                "virtual transaction {}",
+                LockObj::or_unknown(self.virtualxid.as_ref())
+            ),
+            Some("object") => match name {
+                Some(name) => String::from(name),
+                None => format!(
+                    "object {} of catalog {} (subid {}) in database {}",
+                    LockObj::or_unknown(self.objid),
+                    LockObj::or_unknown(self.classid),
+                    LockObj::or_unknown(self.objsubid),
+                    LockObj::or_unknown(self.database)
+                ),
+            },
+            Some("userlock") => format!(
+                "user lock ({}, {})",
+                LockObj::or_unknown(self.classid),
+                LockObj::or_unknown(self.objid)
+            ),
+            Some("advisory") => self.desc_advisory(),
+            Some(kind @ "resource queue") | Some(kind @ "resource group") => match name {
+                Some(name) => format!("{} {}", kind, name),
+                None => format!("{} {}", kind, LockObj::or_unknown(self.objid)),
+            },
+            _ => return self.raw(),
+        };
+        self.on_segment(desc, segment)
+    }
+
+    fn desc_relation(&self, name: Option<&str>) -> String {
+        match name {
+            Some(name) => format!("relation {}", name),
+            None => format!(
+                "relation {} of database {}",
+                LockObj::or_unknown(self.relation),
+                LockObj::or_unknown(self.database)
+            ),
+        }
+    }
+
+    fn on_segment(&self, desc: String, segment: Option<&Segment>) -> String {
+        match self.gp_segment_id {
+            Some(segid) if segid >= 0 => format!("{} on {}", desc, desc_segment(segid, segment)),
+            _ => desc,
+        }
+    }
+
+    // The row a tuple lock is taken on, `name` and `segment` as describe().
+    fn desc_row(&self, name: Option<&str>, segment: Option<&Segment>) -> String {
+        self.on_segment(
+            format!(
+                "row ({},{}) of {}",
+                LockObj::or_unknown(self.page),
+                LockObj::or_unknown(self.tuple),
+                self.desc_relation(name)
+            ),
+            segment,
+        )
+    }
+}
+
+impl LockObj {
+    // (database, relation) of a lock on a relation or a part of it.
+    pub fn relation_key(&self) -> Option<(i64, i64)> {
+        Some((self.database?, self.relation?))
+    }
+
+    pub fn segment(&self) -> Option<i64> {
+        self.gp_segment_id
+    }
+
+    // The same object, maybe on another segment.
+    fn same_but_segment(&self, other: &LockObj) -> bool {
+        let strip = |obj: &LockObj| LockObj {
+            gp_segment_id: None,
+            ..obj.clone()
+        };
+        strip(self) == strip(other)
+    }
+
+    // Locks of the coordinator itself, i.e. what pg_blocking_pids() on the coordinator knows.
+    pub fn on_coordinator(&self) -> bool {
+        matches!(self.gp_segment_id, None | Some(-1)) && !self.is_slot()
+    }
+
+    // Resource queues and resource groups hand out a limited number of slots instead of
+    // conflicting by mode, whoever waits for one waits for every session holding a slot.
+    pub fn is_slot(&self) -> bool {
+        matches!(
+            self.locktype.as_deref(),
+            Some("resource queue") | Some("resource group")
+        )
+    }
+}
+
+impl fmt::Display for LockObj {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.describe(None, None))
     }
 }
 
 // need documentation about the behavior of Eq/Hash on LockMode.
-#[derive(PartialEq, Eq, Hash, Clone, Copy)]
+#[derive(PartialEq, Eq, Hash, Clone)]
 pub enum LockMode {
     AccessExclusiveLock,
     AccessShareLock,
@@ -72,6 +216,10 @@ pub enum LockMode {
     ShareLock,
     ShareRowExclusiveLock,
     ShareUpdateExclusiveLock,
+    // predicate locks of serializable transactions, they never block anyone.
+    SIReadLock,
+    // anything else an extension or a fork may report, see ConflictPolicy.
+    Other(String),
 }
 
 impl From<&str> for LockMode {
@@ -85,7 +233,8 @@ impl From<&str> for LockMode {
             "ShareLock" => LockMode::ShareLock,
             "ShareRowExclusiveLock" => LockMode::ShareRowExclusiveLock,
             "ShareUpdateExclusiveLock" => LockMode::ShareUpdateExclusiveLock,
-            _ => panic!("UnknownLockMode={}", input),
+            "SIReadLock" => LockMode::SIReadLock,
+            _ => LockMode::Other(String::from(input)),
         }
     }
 }
@@ -101,13 +250,44 @@ impl fmt::Display for LockMode {
             LockMode::AccessExclusiveLock => write!(f, "AccessExclusiveLock"),
             LockMode::AccessShareLock => write!(f, "AccessShareLock"),
             LockMode::ShareUpdateExclusiveLock => write!(f, "ShareUpdateExclusiveLock"),
+            LockMode::SIReadLock => write!(f, "SIReadLock"),
+            LockMode::Other(mode) => write!(f, "{}", mode),
+        }
+    }
+}
+
+// Whether a LockMode::Other conflicts with the mode on the other side, we know nothing about it.
+#[derive(PartialEq, Eq, Clone, Copy, Debug, Default)]
+pub enum ConflictPolicy {
+    // conflicts with every mode but SIReadLock, may show waits that are not.
+    #[default]
+    Always,
+    // conflicts with nothing, may miss waits.
+    Never,
+    // conflicts with the same mode only.
+    SameMode,
+}
+
+impl str::FromStr for ConflictPolicy {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "always" => Ok(ConflictPolicy::Always),
+            "never" => Ok(ConflictPolicy::Never),
+            "same" => Ok(ConflictPolicy::SameMode),
+            _ => Err(format!(
+                "UnknownConflictPolicy={}; expected always, never or same",
+                s
+            )),
         }
     }
 }
 
 impl LockMode {
+    // Table-lock conflicts, SIReadLock and Other conflict with nothing here, see conflicts_with().
     pub fn conflict_modes(&self) -> &[Self] {
         match self {
+            LockMode::SIReadLock | LockMode::Other(_) => [].as_ref(),
             LockMode::RowExclusiveLock => [
                 LockMode::ExclusiveLock,
                 LockMode::AccessExclusiveLock,
@@ -169,6 +349,21 @@ impl LockMode {
     }
 }
 
+impl LockMode {
+    // Whether a waiter asking for self is blocked by a holder of `held`.
+    pub fn conflicts_with(&self, held: &LockMode, policy: ConflictPolicy) -> bool {
+        match (self, held) {
+            (LockMode::SIReadLock, _) | (_, LockMode::SIReadLock) => false,
+            (LockMode::Other(_), _) | (_, LockMode::Other(_)) => match policy {
+                ConflictPolicy::Always => true,
+                ConflictPolicy::Never => false,
+                ConflictPolicy::SameMode => self == held,
+            },
+            _ => self.conflict_modes().contains(held),
+        }
+    }
+}
+
 impl str::FromStr for LockMode {
     type Err = Void;
     fn from_str(s: &str) -> Result<Self, Self::Err> {
@@ -179,10 +374,114 @@ impl str::FromStr for LockMode {
 pub type LockObjId = *const LockObj;
 pub type SessionId = i64;
 
-#[derive(Clone, Copy)]
+#[derive(Clone)]
 pub struct Lock {
     pub objid: LockObjId,
     pub mode: LockMode,
+    // the rest is None if the server's pg_locks lacks the column.
+    pub virtualtransaction: Option<String>,
+    pub fastpath: Option<bool>,
+    // when the wait began, and how many seconds ago that was. Always None for granted locks.
+    pub waitstart: Option<String>,
+    pub wait_age: Option<i64>,
+    // the backend holding or waiting for the lock, on its segment. None for prepared xacts.
+    pub pid: Option<i64>,
+}
+
+// A segment that did not answer in LockInfo::get_utility().
+pub struct SegmentError {
+    pub content: i64,
+    pub hostname: String,
+    pub port: u16,
+    pub error: String,
+}
+
+impl fmt::Display for SegmentError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "segment content={} {}:{} failed: {}",
+            self.content, self.hostname, self.port, self.error
+        )
+    }
+}
+
+// Sessions standing for prepared transactions count down from here, far below the negated pids
+// of background workers.
+const PREPARED_SESSID: SessionId = -(1 << 40);
+
+// fetch_rows() reads at most so many rows, each within ROW_TIMEOUT_MS, and keeps at most
+// ROW_CHARS of each.
+const ROWS_MAX: usize = 32;
+const ROW_TIMEOUT_MS: u64 = 1000;
+const ROW_CHARS: usize = 100;
+
+// The contents of a row as it goes into the descriptions: on a single line, without the */ and
+// '/ that would end a comment of the output formats, and cut short.
+fn row_text(row: &str) -> String {
+    let text: String = row
+        .escape_debug()
+        .to_string()
+        .replace("*/", "*\\/")
+        .replace("'/", "'\\/");
+    if text.chars().count() <= ROW_CHARS {
+        return text;
+    }
+    let mut text: String = text.chars().take(ROW_CHARS).collect();
+    text.push_str("...");
+    text
+}
+
+// A wait for a row lock. Who locks a row updates the xmax of the tuple with its transaction,
+// whoever comes next takes the tuple lock and waits for that transaction to end, others wait
+// for the tuple lock behind it.
+pub struct RowWait {
+    // the tuple lock.
+    pub tuple: LockObjId,
+    // the transaction holding the row.
+    pub xid: Option<i64>,
+    // who holds the tuple lock if we wait for it.
+    pub queued_behind: Option<SessionId>,
+}
+
+// A prepared transaction holding locks, from pg_prepared_xacts. It has no session of its own, its
+// SessionId is made up by LockInfo.
+#[derive(Default, Clone)]
+pub struct PreparedXact {
+    pub gid: String,
+    pub prepared: Option<String>,
+    pub owner: Option<String>,
+    pub database: Option<String>,
+}
+
+impl fmt::Display for PreparedXact {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "prepared xact {}", self.gid)?;
+        if let Some(ref prepared) = self.prepared {
+            write!(f, " prepared at {}", prepared)?;
+        }
+        if let Some(ref owner) = self.owner {
+            write!(f, " by {}", owner)?;
+        }
+        if let Some(ref database) = self.database {
+            write!(f, " in {}", database)?;
+        }
+        Ok(())
+    }
+}
+
+// A waiter -> holder pair from gp_dist_wait_status(), what the global deadlock detector sees.
+pub struct DistWait {
+    pub segid: i64,
+    pub waiter: SessionId,
+    pub holder: SessionId,
+    pub waiter_mode: LockMode,
+    pub waiter_locktype: String,
+    // the holder keeps the lock until its transaction ends (a solid edge to the detector), rather
+    // than releasing it at the end of the statement (a dotted one).
+    pub solid: bool,
+    pub waiter_dxid: Option<i64>,
+    pub holder_dxid: Option<i64>,
 }
 
 #[derive(Default)]
@@ -190,9 +489,39 @@ pub struct LockInfo {
     objs: Internment<LockObj>,
     pub granted_table: HashMap<LockObjId, HashMap<LockMode, HashSet<SessionId>>>,
     pub waiter: HashMap<SessionId, Vec<Lock>>,
+    granted: HashMap<SessionId, Vec<Lock>>,
+    // (database, relation) -> schema.relname, only known for relations in the connected database.
+    relnames: HashMap<(i64, i64), String>,
+    // the same, quoted to be used in a query.
+    relidents: HashMap<(i64, i64), String>,
+    // (database, classid, objid, objsubid) -> pg_describe_object(), for object locks in the
+    // connected database and on shared objects.
+    objdescs: HashMap<(i64, i64, i64, i64), String>,
+    // oid -> rsqname.
+    resqueues: HashMap<i64, String>,
+    // oid -> rsgname.
+    resgroups: HashMap<i64, String>,
+    // the made-up sessions of prepared transactions.
+    pub prepared: HashMap<SessionId, PreparedXact>,
+    // (gp_segment_id, xid) -> the made-up session.
+    prepared_xids: HashMap<(i64, i64), SessionId>,
+    // how LockMode::Other conflicts, set before building the WFGraph.
+    pub conflict_policy: ConflictPolicy,
+    // gp_segment_configuration of the primaries when the locks were read, by content.
+    pub segments: HashMap<i64, Segment>,
+    // tuple lock -> the contents of the row, see fetch_rows().
+    rows: HashMap<LockObjId, String>,
+    // relation lock on a partition, index, TOAST table or append-optimized auxiliary relation ->
+    // the same lock on the top-level table, see fetch_rollup().
+    rollup: HashMap<LockObjId, LockObjId>,
 }
 
+// Every LockObjId in a LockInfo points into its own `objs`, whose boxes move along with it, so it
+// can be handed to another thread as a whole.
+unsafe impl Send for LockInfo {}
+
 impl LockInfo {
+    #[allow(clippy::not_unsafe_ptr_arg_deref)]
     pub fn get_obj(&self, objid: LockObjId) -> &LockObj {
         unsafe { &*objid }
     }
@@ -200,7 +529,7 @@ impl LockInfo {
         self.objs.intern(lockobj)
     }
 
-    fn getlock(row: &SimpleQueryRow) -> (LockObj, LockMode, SessionId, bool) {
+    fn getlock(row: &SimpleQueryRow) -> (LockObj, Lock, SessionId, bool) {
         let mut obj = LockObj::default();
 
         macro_rules! fill {
@@ -221,57 +550,1009 @@ impl LockInfo {
         fill!(objid);
         fill!(objsubid);
 
+        // A slot of a resource queue is held in ExclusiveLock whatever mode Greenplum reports,
+        // LockObj::is_slot() decides who blocks whom for them.
+        let mode = if obj.is_slot() {
+            LockMode::ExclusiveLock
+        } else {
+            row.get("mode").unwrap().parse().unwrap()
+        };
+        let lock = Lock {
+            objid: std::ptr::null(),
+            mode,
+            virtualtransaction: row.get("virtualtransaction").map(String::from),
+            fastpath: row.get("fastpath").map(|v| v == "t"),
+            waitstart: row.get("waitstart").map(String::from),
+            wait_age: row.get("wait_age").map(|v| v.parse().unwrap()),
+            pid: row.get("pid").map(|v| v.parse().unwrap()),
+        };
+        let mut sessid: SessionId = row.get("mppsessionid").map_or(0, |v| v.parse().unwrap());
+        // Auxiliary processes and background workers (autovacuum, the global deadlock detector,
+        // ...) have no session, tell them apart by pid instead of lumping them into session 0.
+        // Their pids on different hosts may collide, which merges two of them at worst.
+        if sessid <= 0 {
+            if let Some(pid) = row.get("pid") {
+                sessid = -pid.parse::<SessionId>().unwrap();
+            }
+        }
         (
             obj,
-            row.get("mode").unwrap().parse().unwrap(),
-            row.get("mppsessionid").unwrap().parse().unwrap(),
+            lock,
+            sessid,
             row.get("granted").unwrap().parse().unwrap(),
         )
     }
 
-    fn add_waiter(&mut self, objid: LockObjId, mode: LockMode, sessid: SessionId) {
-        get_or_default(&mut self.waiter, sessid).push(Lock { objid, mode });
+    fn add_waiter(&mut self, lock: Lock, sessid: SessionId) {
+        get_or_default(&mut self.waiter, sessid).push(lock);
     }
 
-    fn add_granted(&mut self, objid: LockObjId, mode: LockMode, sessid: SessionId) {
-        get_or_default(get_or_default(&mut self.granted_table, objid), mode).insert(sessid);
+    fn add_granted(&mut self, lock: Lock, sessid: SessionId) {
+        get_or_default(&mut self.granted_table, lock.objid)
+            .entry(lock.mode.clone())
+            .or_default()
+            .insert(sessid);
+        get_or_default(&mut self.granted, sessid).push(lock);
     }
 
-    fn process_row(&mut self, row: &SimpleQueryRow) {
-        let (lockobj, mode, sessid, granted) = LockInfo::getlock(row);
-        let objid = self.get_objid(lockobj);
+    // Prepared transactions hold their locks without any backend, pg_locks shows them with a
+    // null pid. Each of them holds ExclusiveLock on its own transactionid, which tells its xid
+    // for the virtualtransaction shared by all its locks.
+    fn prepared_vxids(qres: &[SimpleQueryMessage]) -> HashMap<(i64, String), i64> {
+        let mut vxids = HashMap::new();
+        for rowres in qres.iter() {
+            if let SimpleQueryMessage::Row(row) = rowres {
+                if row.get("pid").is_some()
+                    || row.get("locktype") != Some("transactionid")
+                    || row.get("mode") != Some("ExclusiveLock")
+                {
+                    continue;
+                }
+                if let (Some(vxid), Some(xid)) =
+                    (row.get("virtualtransaction"), row.get("transactionid"))
+                {
+                    let segid: i64 = row.get("gp_segment_id").map_or(-1, |v| v.parse().unwrap());
+                    vxids.insert((segid, String::from(vxid)), xid.parse().unwrap());
+                }
+            }
+        }
+        vxids
+    }
+
+    fn prepared_sessid(
+        &mut self,
+        row: &SimpleQueryRow,
+        vxids: &HashMap<(i64, String), i64>,
+    ) -> Option<SessionId> {
+        if row.get("pid").is_some() {
+            return None;
+        }
+        let segid: i64 = row.get("gp_segment_id").map_or(-1, |v| v.parse().unwrap());
+        // without virtualtransaction (Greenplum 4) all of them on a segment look the same.
+        let xid = row
+            .get("virtualtransaction")
+            .and_then(|vxid| vxids.get(&(segid, String::from(vxid))))
+            .copied();
+        let key = (segid, xid.unwrap_or(-1));
+        if let Some(&sessid) = self.prepared_xids.get(&key) {
+            return Some(sessid);
+        }
+        // not in pg_prepared_xacts, e.g. prepared after we looked, known by its xid only.
+        let sessid = PREPARED_SESSID - self.prepared.len() as SessionId;
+        let gid = match xid {
+            Some(xid) => format!("xid {} on segment {}", xid, segid),
+            None => format!("? on segment {}", segid),
+        };
+        self.prepared.insert(
+            sessid,
+            PreparedXact {
+                gid,
+                ..PreparedXact::default()
+            },
+        );
+        self.prepared_xids.insert(key, sessid);
+        Some(sessid)
+    }
+
+    fn process_row(&mut self, row: &SimpleQueryRow, vxids: &HashMap<(i64, String), i64>) {
+        let (lockobj, mut lock, mut sessid, granted) = LockInfo::getlock(row);
+        if let Some(prepared) = self.prepared_sessid(row, vxids) {
+            sessid = prepared;
+        }
+        lock.objid = self.get_objid(lockobj);
         if !granted {
-            self.add_waiter(objid, mode, sessid);
+            self.add_waiter(lock, sessid);
         } else {
-            self.add_granted(objid, mode, sessid);
+            lock.wait_age = None;
+            lock.waitstart = None;
+            self.add_granted(lock, sessid);
         }
     }
 
-    fn process(qres: Vec<SimpleQueryMessage>) -> LockInfo {
-        let mut lockinfo = LockInfo::default();
+    fn process_rows(&mut self, qres: &[SimpleQueryMessage]) {
+        let vxids = LockInfo::prepared_vxids(qres);
         for rowres in qres.iter() {
             if let SimpleQueryMessage::Row(row) = rowres {
-                lockinfo.process_row(row);
+                self.process_row(row, &vxids);
             }
         }
-        lockinfo
     }
 
-    pub fn get(cli: &mut GPDBCli) -> LockInfo {
-        let qstr = if cli.ver.semver.major <= 4 {
-            "select null as virtualxid,\
-            gp_segment_id,locktype,database,relation,page,tuple,\
-            transactionid,classid,objid,objsubid,mode,\
-            case when granted = 't' then 'true' else 'false' end as granted,\
-            mppsessionid from pg_locks"
+    // pg_prepared_xacts of the server we are connected to, as segment `segid`.
+    fn prepared_query(segid: i64) -> String {
+        format!(
+            "select {} as gp_segment_id, transaction, gid, prepared::text, owner, database \
+            from pg_prepared_xacts",
+            segid
+        )
+    }
+
+    // One PreparedXact per gid, since a distributed transaction is prepared under the same gid
+    // everywhere. The rows of a segment must be processed before its locks.
+    fn process_prepared(&mut self, qres: &[SimpleQueryMessage]) {
+        for rowres in qres.iter() {
+            if let SimpleQueryMessage::Row(row) = rowres {
+                let segid: i64 = row.get("gp_segment_id").unwrap().parse().unwrap();
+                let xid: i64 = row.get("transaction").unwrap().parse().unwrap();
+                let gid = row.get("gid").unwrap();
+                let known = self
+                    .prepared
+                    .iter()
+                    .find(|(_, prepared)| prepared.gid == gid)
+                    .map(|(&sessid, _)| sessid);
+                let sessid = known.unwrap_or(PREPARED_SESSID - self.prepared.len() as SessionId);
+                self.prepared_xids.insert((segid, xid), sessid);
+                self.prepared.entry(sessid).or_insert_with(|| PreparedXact {
+                    gid: String::from(gid),
+                    prepared: row.get("prepared").map(String::from),
+                    owner: row.get("owner").map(String::from),
+                    database: row.get("database").map(String::from),
+                });
+            }
+        }
+    }
+
+    // The prepared transactions of the coordinator and, if `dispatch`, of every segment. Must be
+    // done before process_rows(). Errors are ignored, the locks of the prepared transactions
+    // just end up known by their xid only.
+    fn fetch_prepared(&mut self, cli: &mut GPDBCli, dispatch: bool) {
+        let local = LockInfo::prepared_query(-1);
+        let qres = if dispatch && cli.ver.caps.gp_segment_id {
+            let qstr = format!(
+                "{} union all select gp_segment_id, transaction, gid, prepared::text, owner, \
+                database from gp_dist_random('pg_prepared_xacts')",
+                local
+            );
+            cli.try_query(qstr.as_str())
+                .or_else(|_| cli.try_query(local.as_str()))
         } else {
-            "select virtualxid,\
-            gp_segment_id,locktype,database,relation,page,tuple,\
+            cli.try_query(local.as_str())
+        };
+        if let Ok(qres) = qres {
+            self.process_prepared(&qres);
+        }
+    }
+
+    // Plain PostgreSQL has neither gp_segment_id nor mppsessionid, every backend is a session
+    // on the coordinator there. Columns the server lacks are selected as null.
+    fn lock_query(cli: &GPDBCli) -> String {
+        let caps = &cli.ver.caps;
+        let column = |has: bool, column: &'static str, alias: &'static str| {
+            if has {
+                String::from(column)
+            } else {
+                format!("null as {}", alias)
+            }
+        };
+        format!(
+            "select {},{},locktype,database,relation,page,tuple,\
             transactionid,classid,objid,objsubid,mode,\
             case when granted = 't' then 'true' else 'false' end as granted,\
-            mppsessionid from pg_locks"
+            pid,{},{},{},{},{} from pg_locks",
+            column(caps.virtualxid, "virtualxid", "virtualxid"),
+            if caps.gp_segment_id {
+                "gp_segment_id"
+            } else {
+                "-1 as gp_segment_id"
+            },
+            if caps.gp_segment_id {
+                "mppsessionid"
+            } else {
+                "pid as mppsessionid"
+            },
+            // virtualtransaction came with virtualxid in PostgreSQL 8.3.
+            column(caps.virtualxid, "virtualtransaction", "virtualtransaction"),
+            column(caps.fastpath, "fastpath", "fastpath"),
+            column(caps.waitstart, "waitstart", "waitstart"),
+            column(
+                caps.waitstart,
+                "extract(epoch from now() - waitstart)::bigint as wait_age",
+                "wait_age"
+            ),
+        )
+    }
+
+    pub fn get(cli: &mut GPDBCli) -> Result<LockInfo, GPDBError> {
+        let mut lockinfo = LockInfo::default();
+        lockinfo.fetch_prepared(cli, true);
+        if cli.ver.caps.gp_segment_id {
+            lockinfo.segments = cli
+                .primaries()?
+                .into_iter()
+                .map(|segment| (segment.content, segment))
+                .collect();
+        }
+        lockinfo.process_rows(&cli.try_query(LockInfo::lock_query(cli).as_str())?);
+        lockinfo.fetch_resgroup(cli)?;
+        lockinfo.fetch_names(cli)?;
+        Ok(lockinfo)
+    }
+
+    // Needs Capabilities::gdd.
+    pub fn get_dist_wait(cli: &mut GPDBCli) -> Result<Vec<DistWait>, GPDBError> {
+        let mut waits = vec![];
+        let qres = cli.try_query(
+            "select segid, waiter_dxid, holder_dxid, \
+            case when \"holdTillEndXact\" then 'true' else 'false' end as solid, \
+            waiter_lockmode, waiter_locktype, waiter_sessionid, holder_sessionid \
+            from gp_dist_wait_status()",
+        )?;
+        for rowres in qres.iter() {
+            if let SimpleQueryMessage::Row(row) = rowres {
+                waits.push(DistWait {
+                    segid: row.get("segid").unwrap().parse().unwrap(),
+                    waiter: row.get("waiter_sessionid").unwrap().parse().unwrap(),
+                    holder: row.get("holder_sessionid").unwrap().parse().unwrap(),
+                    waiter_mode: row.get("waiter_lockmode").unwrap().parse().unwrap(),
+                    waiter_locktype: String::from(row.get("waiter_locktype").unwrap()),
+                    solid: row.get("solid").unwrap().parse().unwrap(),
+                    waiter_dxid: row.get("waiter_dxid").map(|v| v.parse().unwrap()),
+                    holder_dxid: row.get("holder_dxid").map(|v| v.parse().unwrap()),
+                });
+            }
+        }
+        Ok(waits)
+    }
+
+    // waiter -> blocker session pairs the coordinator reports by pg_blocking_pids(), needs
+    // Capabilities::blocking_pids. Besides the holders of conflicting locks, it includes the
+    // waiters queued ahead in a conflicting mode.
+    pub fn get_blocking_pids(
+        cli: &mut GPDBCli,
+    ) -> Result<HashSet<(SessionId, SessionId)>, GPDBError> {
+        let sessid = if cli.ver.caps.gp_segment_id {
+            "sess_id"
+        } else {
+            "pid"
+        };
+        let qstr = format!(
+            "select w.{0} as waiter, h.{0} as holder \
+            from pg_stat_activity w, unnest(pg_blocking_pids(w.pid)) b(pid), pg_stat_activity h \
+            where w.wait_event_type = 'Lock' and h.pid = b.pid",
+            sessid
+        );
+        let mut pairs = HashSet::new();
+        for rowres in cli.try_query(qstr.as_str())?.iter() {
+            if let SimpleQueryMessage::Row(row) = rowres {
+                let waiter: SessionId = row.get("waiter").unwrap().parse().unwrap();
+                let holder: SessionId = row.get("holder").unwrap().parse().unwrap();
+                if waiter != holder {
+                    pairs.insert((waiter, holder));
+                }
+            }
+        }
+        Ok(pairs)
+    }
+
+    // The lock `wait` is about: the waiter's lock in pg_locks on the same segment if there is
+    // one, else a lock on an object known by its locktype only, pg_locks may have moved on since.
+    pub fn dist_wait_lock(&mut self, wait: &DistWait) -> Lock {
+        let found = self.waiter.get(&wait.waiter).and_then(|locks| {
+            locks.iter().find(|lock| {
+                let obj = self.get_obj(lock.objid);
+                lock.mode == wait.waiter_mode
+                    && obj.gp_segment_id == Some(wait.segid)
+                    && obj.locktype.as_deref() == Some(wait.waiter_locktype.as_str())
+            })
+        });
+        if let Some(lock) = found {
+            return lock.clone();
+        }
+        let lockobj = LockObj {
+            locktype: Some(wait.waiter_locktype.clone()),
+            gp_segment_id: Some(wait.segid),
+            ..LockObj::default()
+        };
+        Lock {
+            objid: self.get_objid(lockobj),
+            mode: wait.waiter_mode.clone(),
+            virtualtransaction: None,
+            fastpath: None,
+            waitstart: None,
+            wait_age: None,
+            pid: None,
+        }
+    }
+
+    // What the holder of `wait` holds on `lock`, a mode of our own if pg_locks does not tell.
+    pub fn dist_wait_hold(&self, wait: &DistWait, lock: &Lock) -> LockMode {
+        self.get_all_holders(lock.objid)
+            .into_iter()
+            .find(|(mode, holder)| {
+                *holder == wait.holder && lock.mode.conflicts_with(mode, self.conflict_policy)
+            })
+            .map(|(mode, _)| mode)
+            .unwrap_or_else(|| LockMode::Other(String::from("unknown")))
+    }
+
+    // Resource group slots are not locks, the coordinator's pg_stat_activity tells who waits
+    // for a slot and who holds one, i.e. runs a transaction in the group. They become locks on
+    // a "resource group" LockObj.
+    fn fetch_resgroup(&mut self, cli: &mut GPDBCli) -> Result<(), GPDBError> {
+        if !cli.ver.caps.resgroup {
+            return Ok(());
+        }
+        let waiting = if cli.ver.caps.gp_role {
+            "wait_event_type = 'ResourceGroup'"
+        } else {
+            "waiting_reason = 'resgroup'"
+        };
+        let qstr = format!(
+            "select sess_id, rsgid, rsgname, \
+            case when {0} then 'true' else 'false' end as waiting \
+            from pg_stat_activity where rsgid > 0 and ({0} or xact_start is not null)",
+            waiting
+        );
+        for rowres in cli.try_query(qstr.as_str())?.iter() {
+            if let SimpleQueryMessage::Row(row) = rowres {
+                let sessid: SessionId = row.get("sess_id").unwrap().parse().unwrap();
+                let rsgid: i64 = row.get("rsgid").unwrap().parse().unwrap();
+                let waiting: bool = row.get("waiting").unwrap().parse().unwrap();
+                let lockobj = LockObj {
+                    locktype: Some(String::from("resource group")),
+                    gp_segment_id: Some(-1),
+                    objid: Some(rsgid),
+                    ..LockObj::default()
+                };
+                self.resgroups
+                    .insert(rsgid, String::from(row.get("rsgname").unwrap()));
+                let lock = Lock {
+                    objid: self.get_objid(lockobj),
+                    mode: LockMode::ExclusiveLock,
+                    virtualtransaction: None,
+                    fastpath: None,
+                    waitstart: None,
+                    wait_age: None,
+                    pid: None,
+                };
+                if waiting {
+                    self.add_waiter(lock, sessid);
+                } else {
+                    self.add_granted(lock, sessid);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    // Instead of letting the coordinator dispatch pg_locks, connect to every primary (coordinator
+    // included) in utility mode in parallel and merge their local pg_locks. Used when the
+    // coordinator or the dispatch is stuck. Segments that fail or do not answer within `timeout`
+    // are returned, their locks are missing from the LockInfo. None waits for them forever.
+    pub fn get_utility(
+        cli: &mut GPDBCli,
+        timeout: Option<Duration>,
+    ) -> Result<(LockInfo, Vec<SegmentError>), GPDBError> {
+        let segments = cli.primaries()?;
+        let qstr = LockInfo::lock_query(cli);
+        let (tx, rx) = mpsc::channel();
+        for (idx, segment) in segments.iter().enumerate() {
+            let ucfg = cli.get_utility_config(segment.hostname.as_str(), segment.port);
+            let tx = tx.clone();
+            let qstr = qstr.clone();
+            // the coordinator's own are read over `cli`, see below.
+            let prepared = Some(segment.content)
+                .filter(|&content| content >= 0)
+                .map(LockInfo::prepared_query);
+            thread::spawn(move || {
+                let res = GPDBCli::new_utility(ucfg, timeout).and_then(|mut segcli| {
+                    let prepared = match prepared {
+                        Some(prepared) => segcli.try_query(prepared.as_str()).unwrap_or_default(),
+                        None => vec![],
+                    };
+                    Ok((prepared, segcli.try_query(qstr.as_str())?))
+                });
+                let _ = tx.send((idx, res.map_err(|e| e.to_string())));
+            });
+        }
+        drop(tx);
+
+        // connect_timeout and statement_timeout should be enough, but a segment may hang in a way
+        // neither of them notices, do not wait for it forever.
+        let deadline = timeout.map(|timeout| Instant::now() + timeout * 2);
+        let mut lockinfo = LockInfo {
+            segments: segments
+                .iter()
+                .map(|segment| (segment.content, segment.clone()))
+                .collect(),
+            ..LockInfo::default()
         };
-        LockInfo::process(cli.query(qstr))
+        // dispatching gp_dist_random() may be what is stuck, every segment reads its own.
+        lockinfo.fetch_prepared(cli, false);
+        let mut errors: HashMap<usize, String> = HashMap::new();
+        let mut answered = HashSet::new();
+        while answered.len() < segments.len() {
+            let received = match deadline {
+                Some(deadline) => rx
+                    .recv_timeout(deadline.saturating_duration_since(Instant::now()))
+                    .ok(),
+                None => rx.recv().ok(),
+            };
+            match received {
+                Some((idx, Ok((prepared, qres)))) => {
+                    lockinfo.process_prepared(&prepared);
+                    lockinfo.process_rows(&qres);
+                    answered.insert(idx);
+                }
+                Some((idx, Err(err))) => {
+                    errors.insert(idx, err);
+                    answered.insert(idx);
+                }
+                None => break,
+            }
+        }
+
+        let mut failed = vec![];
+        for (idx, segment) in segments.into_iter().enumerate() {
+            let error = if !answered.contains(&idx) {
+                match timeout {
+                    Some(timeout) => format!("no response within {:?}", timeout * 2),
+                    None => String::from("no response"),
+                }
+            } else if let Some(error) = errors.remove(&idx) {
+                error
+            } else {
+                continue;
+            };
+            failed.push(SegmentError {
+                content: segment.content,
+                hostname: segment.hostname,
+                port: segment.port,
+                error,
+            });
+        }
+
+        lockinfo.fetch_resgroup(cli)?;
+        lockinfo.fetch_names(cli)?;
+        Ok((lockinfo, failed))
+    }
+
+    // child relation -> the relation it belongs to, for the relations in `relids` and their
+    // ancestors: indexes to their table, partitions to their parent (Greenplum 4 to 6 partitions
+    // are inheritance children too), TOAST tables to their owner, and the segment, block
+    // directory and visibility map relations of append-optimized tables to the table.
+    fn fetch_parents(
+        cli: &mut GPDBCli,
+        relids: HashSet<i64>,
+    ) -> Result<HashMap<i64, i64>, GPDBError> {
+        let mut parts = vec![
+            String::from("select indexrelid as child, indrelid as parent from pg_index"),
+            String::from("select inhrelid, inhparent from pg_inherits"),
+            String::from("select reltoastrelid, oid from pg_class where reltoastrelid <> 0"),
+        ];
+        if cli.ver.caps.gp_segment_id {
+            // which of them pg_appendonly has depends on the version.
+            let columns = cli.columns("pg_appendonly").unwrap_or_default();
+            for aux in &[
+                "segrelid",
+                "blkdirrelid",
+                "blkdiridxid",
+                "visimaprelid",
+                "visimapidxid",
+            ] {
+                if columns.contains(*aux) {
+                    parts.push(format!(
+                        "select {0}, relid from pg_appendonly where {0} <> 0",
+                        aux
+                    ));
+                }
+            }
+        }
+        let query = parts.join(" union all ");
+
+        let mut parents = HashMap::new();
+        let mut queried: HashSet<i64> = HashSet::new();
+        let mut todo: Vec<i64> = relids.into_iter().collect();
+        while !todo.is_empty() {
+            queried.extend(todo.iter().copied());
+            let relids: Vec<String> = todo.iter().map(|relid| relid.to_string()).collect();
+            let qstr = format!(
+                "select child, parent from ({}) p where child in ({})",
+                query,
+                relids.join(",")
+            );
+            todo.clear();
+            for rowres in cli.try_query(qstr.as_str())?.iter() {
+                if let SimpleQueryMessage::Row(row) = rowres {
+                    let child: i64 = row.get("child").unwrap().parse().unwrap();
+                    let parent: i64 = row.get("parent").unwrap().parse().unwrap();
+                    parents.insert(child, parent);
+                    if !queried.contains(&parent) {
+                        todo.push(parent);
+                    }
+                }
+            }
+            todo.sort_unstable();
+            todo.dedup();
+        }
+        Ok(parents)
+    }
+
+    // A query on a partitioned append-optimized table locks every partition, their indexes,
+    // TOAST tables and auxiliary relations. Map the relation locks on all of them to the same
+    // lock on the top-level table, WFGraph merges their edges then. Only relation locks are
+    // rolled up, pages and rows stay where they are.
+    pub fn fetch_rollup(&mut self, cli: &mut GPDBCli) -> Result<(), GPDBError> {
+        // the catalogs we can read only tell about the relations of the connected database, the
+        // locks in other databases stay as they are.
+        let database: Option<i64> = cli
+            .try_query("select oid from pg_database where datname = current_database()")?
+            .iter()
+            .find_map(|rowres| match rowres {
+                SimpleQueryMessage::Row(row) => row.get(0).map(|v| v.parse().unwrap()),
+                _ => None,
+            });
+        let local = |obj: &LockObj| {
+            obj.locktype.as_deref() == Some("relation")
+                && obj.database.is_some()
+                && obj.database == database
+        };
+        let relids: HashSet<i64> = self
+            .objs
+            .0
+            .iter()
+            .filter(|obj| local(obj))
+            .filter_map(|obj| obj.relation)
+            .collect();
+        if relids.is_empty() {
+            return Ok(());
+        }
+        let parents = LockInfo::fetch_parents(cli, relids)?;
+        let root = |mut relid: i64| {
+            let mut seen = HashSet::new();
+            while let Some(&parent) = parents.get(&relid) {
+                if !seen.insert(relid) {
+                    break;
+                }
+                relid = parent;
+            }
+            relid
+        };
+        let children: Vec<(LockObjId, LockObj)> = self
+            .objs
+            .0
+            .iter()
+            .filter(|obj| local(obj))
+            .filter_map(|obj| {
+                let relid = obj.relation?;
+                let top = root(relid);
+                if top == relid {
+                    return None;
+                }
+                let objid: LockObjId = &**obj;
+                Some((
+                    objid,
+                    LockObj {
+                        relation: Some(top),
+                        ..(**obj).clone()
+                    },
+                ))
+            })
+            .collect();
+        for (objid, top) in children {
+            let topid = self.get_objid(top);
+            self.rollup.insert(objid, topid);
+        }
+        self.fetch_relnames(cli)
+    }
+
+    // The lock on the top-level table objid is rolled up to, if it is.
+    pub fn rolled_up(&self, objid: LockObjId) -> Option<LockObjId> {
+        self.rollup.get(&objid).copied()
+    }
+
+    pub fn has_rollup(&self) -> bool {
+        !self.rollup.is_empty()
+    }
+
+    // Names of the objects behind the locks, see LockObj::describe().
+    fn fetch_names(&mut self, cli: &mut GPDBCli) -> Result<(), GPDBError> {
+        self.fetch_relnames(cli)?;
+        if cli.ver.caps.describe_object {
+            self.fetch_objdescs(cli)?;
+        }
+        if cli.ver.caps.gp_segment_id
+            && self
+                .objs
+                .0
+                .iter()
+                .any(|obj| obj.locktype.as_deref() == Some("resource queue"))
+        {
+            for rowres in cli
+                .try_query("select oid, rsqname from pg_resqueue")?
+                .iter()
+            {
+                if let SimpleQueryMessage::Row(row) = rowres {
+                    let oid: i64 = row.get("oid").unwrap().parse().unwrap();
+                    let rsqname = String::from(row.get("rsqname").unwrap());
+                    self.resqueues.insert(oid, rsqname);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn fetch_objdescs(&mut self, cli: &mut GPDBCli) -> Result<(), GPDBError> {
+        let objs: HashSet<String> = self
+            .objs
+            .0
+            .iter()
+            .filter(|obj| obj.locktype.as_deref() == Some("object"))
+            .filter_map(|obj| {
+                Some(format!(
+                    "({},{},{},{})",
+                    obj.database?, obj.classid?, obj.objid?, obj.objsubid?
+                ))
+            })
+            .collect();
+        if objs.is_empty() {
+            return Ok(());
+        }
+        let objs: Vec<String> = objs.into_iter().collect();
+        let qstr = format!(
+            "select database, classid, objid, objsubid, \
+            pg_describe_object(classid::oid, objid::oid, objsubid) as descr \
+            from (values {}) v(database, classid, objid, objsubid), pg_database d \
+            where d.datname = current_database() and v.database in (0, d.oid)",
+            objs.join(",")
+        );
+        for rowres in cli.try_query(qstr.as_str())?.iter() {
+            if let SimpleQueryMessage::Row(row) = rowres {
+                // null if the object has been dropped meanwhile.
+                if let Some(descr) = row.get("descr") {
+                    let key = (
+                        row.get("database").unwrap().parse().unwrap(),
+                        row.get("classid").unwrap().parse().unwrap(),
+                        row.get("objid").unwrap().parse().unwrap(),
+                        row.get("objsubid").unwrap().parse().unwrap(),
+                    );
+                    self.objdescs.insert(key, String::from(descr));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn fetch_relnames(&mut self, cli: &mut GPDBCli) -> Result<(), GPDBError> {
+        let relids: HashSet<i64> = self.objs.0.iter().filter_map(|obj| obj.relation).collect();
+        if relids.is_empty() {
+            return Ok(());
+        }
+        let relids: Vec<String> = relids.iter().map(|relid| relid.to_string()).collect();
+        let qstr = format!(
+            "select d.oid as database, c.oid as relation, \
+            n.nspname || '.' || c.relname as relname, \
+            quote_ident(n.nspname) || '.' || quote_ident(c.relname) as relident \
+            from pg_class c join pg_namespace n on n.oid = c.relnamespace, pg_database d \
+            where d.datname = current_database() and c.oid in ({})",
+            relids.join(",")
+        );
+        for rowres in cli.try_query(qstr.as_str())?.iter() {
+            if let SimpleQueryMessage::Row(row) = rowres {
+                let database: i64 = row.get("database").unwrap().parse().unwrap();
+                let relation: i64 = row.get("relation").unwrap().parse().unwrap();
+                let relname = String::from(row.get("relname").unwrap());
+                self.relnames.insert((database, relation), relname);
+                let relident = String::from(row.get("relident").unwrap());
+                self.relidents.insert((database, relation), relident);
+            }
+        }
+        Ok(())
+    }
+
+    pub fn get_relname(&self, objid: LockObjId) -> Option<&str> {
+        let obj = self.get_obj(objid);
+        self.relnames
+            .get(&(obj.database?, obj.relation?))
+            .map(String::as_str)
+    }
+
+    fn get_name(&self, objid: LockObjId) -> Option<&str> {
+        let obj = self.get_obj(objid);
+        match obj.locktype.as_deref() {
+            Some("object") => self
+                .objdescs
+                .get(&(obj.database?, obj.classid?, obj.objid?, obj.objsubid?))
+                .map(String::as_str),
+            Some("resource queue") => self.resqueues.get(&obj.objid?).map(String::as_str),
+            Some("resource group") => self.resgroups.get(&obj.objid?).map(String::as_str),
+            _ => self.get_relname(objid),
+        }
+    }
+
+    fn get_segment(&self, segid: Option<i64>) -> Option<&Segment> {
+        self.segments.get(&segid?)
+    }
+
+    // Where the object of objid is, None on the coordinator of PostgreSQL.
+    pub fn get_segment_of(&self, objid: LockObjId) -> Option<&Segment> {
+        self.get_segment(self.get_obj(objid).gp_segment_id)
+    }
+
+    pub fn desc_segment(&self, segid: i64) -> String {
+        desc_segment(segid, self.segments.get(&segid))
+    }
+
+    pub fn desc_obj(&self, objid: LockObjId) -> String {
+        let obj = self.get_obj(objid);
+        obj.describe(self.get_name(objid), self.get_segment(obj.gp_segment_id))
+    }
+
+    // All locks granted to sessid.
+    pub fn get_granted(&self, sessid: SessionId) -> &[Lock] {
+        self.granted.get(&sessid).map(Vec::as_slice).unwrap_or(&[])
+    }
+
+    // Every granted lock of every session.
+    pub fn all_granted(&self) -> impl Iterator<Item = (SessionId, &Lock)> + '_ {
+        self.granted
+            .iter()
+            .flat_map(|(&sessid, locks)| locks.iter().map(move |lock| (sessid, lock)))
+    }
+
+    // A best guess why sessid waits for `lock` while nobody is holding it in a conflicting mode.
+    pub fn guess_unexplained(&self, sessid: SessionId, lock: &Lock) -> String {
+        let obj = self.get_obj(lock.objid);
+        if obj.is_slot() {
+            return String::from("nobody holds a slot, one may have been freed after the snapshot");
+        }
+        let conflicting = |objid: LockObjId| {
+            self.get_all_holders(objid).iter().any(|(mode, holder)| {
+                *holder != sessid && lock.mode.conflicts_with(mode, self.conflict_policy)
+            })
+        };
+        let mut segments: Vec<i64> = self
+            .objs
+            .0
+            .iter()
+            .filter(|other| obj.same_but_segment(other))
+            .filter(|other| conflicting(&***other as LockObjId))
+            .filter_map(|other| other.gp_segment_id)
+            .collect();
+        if !segments.is_empty() {
+            segments.sort_unstable();
+            let segments: Vec<String> = segments.iter().map(i64::to_string).collect();
+            return format!(
+                "held in a conflicting mode on segment {} only, the segments' snapshots are skewed",
+                segments.join(", ")
+            );
+        }
+        // the lock queue: a conflicting waiter that has been waiting longer goes first.
+        let mut queued: Vec<SessionId> = self
+            .waiter
+            .iter()
+            .filter(|(&other, _)| other != sessid)
+            .filter(|(_, locks)| {
+                locks.iter().any(|other| {
+                    other.objid == lock.objid
+                        && lock.mode.conflicts_with(&other.mode, self.conflict_policy)
+                        && match (other.wait_age, lock.wait_age) {
+                            (Some(theirs), Some(ours)) => theirs >= ours,
+                            _ => true,
+                        }
+                })
+            })
+            .map(|(&other, _)| other)
+            .collect();
+        if !queued.is_empty() {
+            queued.sort_unstable();
+            let queued: Vec<String> = queued.iter().map(SessionId::to_string).collect();
+            return format!(
+                "queued behind session {} waiting in a conflicting mode",
+                queued.join(", ")
+            );
+        }
+        match obj.locktype.as_deref() {
+            Some("transactionid") | Some("virtualxid") => {
+                String::from("the transaction it waits for ended after the snapshot")
+            }
+            Some("relation")
+                if lock
+                    .mode
+                    .conflicts_with(&LockMode::RowExclusiveLock, self.conflict_policy) =>
+            {
+                String::from(
+                    "conflicting fast-path locks may be missing from the pg_locks snapshot",
+                )
+            }
+            _ => String::from("the holder released it after the snapshot"),
+        }
+    }
+
+    // The row sessid waits for when it waits for objid, if it is a row lock: the tuple lock, or a
+    // transaction while holding a tuple lock on the same segment.
+    pub fn row_wait(&self, sessid: SessionId, objid: LockObjId) -> Option<RowWait> {
+        let obj = self.get_obj(objid);
+        let is_tuple = |lock: &&Lock| {
+            let tuple = self.get_obj(lock.objid);
+            tuple.locktype.as_deref() == Some("tuple") && tuple.gp_segment_id == obj.gp_segment_id
+        };
+        match obj.locktype.as_deref() {
+            Some("transactionid") => {
+                let tuple = self.get_granted(sessid).iter().find(is_tuple)?;
+                Some(RowWait {
+                    tuple: tuple.objid,
+                    xid: obj.transactionid,
+                    queued_behind: None,
+                })
+            }
+            Some("tuple") => {
+                let mut holders: Vec<SessionId> = self
+                    .get_all_holders(objid)
+                    .into_iter()
+                    .map(|(_, holder)| holder)
+                    .filter(|&holder| holder != sessid)
+                    .collect();
+                holders.sort_unstable();
+                let queued_behind = holders.first().copied();
+                let xid = queued_behind.and_then(|holder| {
+                    self.waiter.get(&holder)?.iter().find_map(|lock| {
+                        let waited = self.get_obj(lock.objid);
+                        if waited.locktype.as_deref() == Some("transactionid")
+                            && waited.gp_segment_id == obj.gp_segment_id
+                        {
+                            waited.transactionid
+                        } else {
+                            None
+                        }
+                    })
+                });
+                Some(RowWait {
+                    tuple: objid,
+                    xid,
+                    queued_behind,
+                })
+            }
+            _ => None,
+        }
+    }
+
+    // "row (page,tuple) of schema.table on segment N held by transaction X, contents (...)".
+    pub fn desc_row_wait(&self, wait: &RowWait) -> String {
+        let mut desc = self.get_obj(wait.tuple).desc_row(
+            self.get_relname(wait.tuple),
+            self.get_segment_of(wait.tuple),
+        );
+        if let Some(xid) = wait.xid {
+            desc = format!("{} held by transaction {}", desc, xid);
+        }
+        if let Some(row) = self.rows.get(&wait.tuple) {
+            desc = format!("{}, contents {}", desc, row);
+        }
+        desc
+    }
+
+    // What the rows sessions wait for contain, to tell which records are contended. Rows gone or
+    // invisible to our snapshot are left out. The relations are contended by definition, every
+    // lookup gets a short statement_timeout and lock_timeout of its own.
+    pub fn fetch_rows(&mut self, cli: &mut GPDBCli) {
+        let mut tuples: Vec<LockObjId> = self
+            .waiter
+            .iter()
+            .flat_map(|(&sessid, locks)| locks.iter().map(move |lock| (sessid, lock.objid)))
+            .filter_map(|(sessid, objid)| Some(self.row_wait(sessid, objid)?.tuple))
+            .collect();
+        tuples.sort_unstable();
+        tuples.dedup();
+        if tuples.len() > ROWS_MAX {
+            eprintln!("fetching {} of {} rows", ROWS_MAX, tuples.len());
+            tuples.truncate(ROWS_MAX);
+        }
+
+        let mut settings = vec!["statement_timeout"];
+        if cli.ver.caps.lock_timeout {
+            settings.push("lock_timeout");
+        }
+        let mut saved = vec![];
+        for setting in settings {
+            let old = cli
+                .try_query(format!("show {}", setting).as_str())
+                .ok()
+                .and_then(|qres| {
+                    qres.iter().find_map(|rowres| match rowres {
+                        SimpleQueryMessage::Row(row) => row.get(0).map(String::from),
+                        _ => None,
+                    })
+                });
+            let set = format!("set {} = {}", setting, ROW_TIMEOUT_MS);
+            if let (Some(old), Ok(_)) = (old, cli.try_query(set.as_str())) {
+                saved.push((setting, old));
+            }
+        }
+
+        for tuple in tuples {
+            let obj = self.get_obj(tuple);
+            let (page, offset) = match (obj.page, obj.tuple) {
+                (Some(page), Some(offset)) => (page, offset),
+                _ => continue,
+            };
+            // only the relations of the connected database are known, a row of another database
+            // cannot be read from here.
+            let relname = match obj.relation_key().and_then(|key| self.relidents.get(&key)) {
+                Some(relname) => relname.clone(),
+                None => continue,
+            };
+            let segment = match obj.gp_segment_id {
+                Some(segid) if segid >= 0 => format!(" and gp_segment_id = {}", segid),
+                _ => String::new(),
+            };
+            let qstr = format!(
+                "select r::text as row from {} r where ctid = '({},{})'{}",
+                relname, page, offset, segment
+            );
+            let row = match cli.try_query(qstr.as_str()) {
+                Ok(qres) => qres.iter().find_map(|rowres| match rowres {
+                    SimpleQueryMessage::Row(row) => row.get("row").map(String::from),
+                    _ => None,
+                }),
+                Err(err) => {
+                    eprintln!(
+                        "cannot fetch {}: {}",
+                        obj.desc_row(self.get_relname(tuple), None),
+                        err
+                    );
+                    None
+                }
+            };
+            if let Some(row) = row {
+                self.rows.insert(tuple, row_text(row.as_str()));
+            }
+        }
+
+        for (setting, old) in saved {
+            let set = format!("set {} = '{}'", setting, old);
+            if let Err(err) = cli.try_query(set.as_str()) {
+                eprintln!("cannot restore {}: {}", setting, err);
+            }
+        }
+    }
+
+    // Every session holding objid in whatever mode.
+    pub fn get_all_holders(&self, objid: LockObjId) -> Vec<(LockMode, SessionId)> {
+        let mut holders = vec![];
+        if let Some(modes) = self.granted_table.get(&objid) {
+            for (mode, sessions) in modes.iter() {
+                holders.extend(sessions.iter().map(|&sessid| (mode.clone(), sessid)));
+            }
+        }
+        holders
+    }
+
+    // The modes sessid holds objid in, while waiting for it they are the locks being upgraded.
+    pub fn held_modes(&self, sessid: SessionId, objid: LockObjId) -> Vec<LockMode> {
+        let mut modes: Vec<LockMode> = self
+            .granted_table
+            .get(&objid)
+            .map(|modes| {
+                modes
+                    .iter()
+                    .filter(|(_, sessions)| sessions.contains(&sessid))
+                    .map(|(mode, _)| mode.clone())
+                    .collect()
+            })
+            .unwrap_or_default();
+        modes.sort_by_key(LockMode::to_string);
+        modes
     }
 
     pub fn get_holders(&self, lockmode: LockMode, objid: LockObjId) -> Option<&HashSet<SessionId>> {
@@ -0,0 +1,176 @@
+/*
+Copyright 2020 <盏一 w@hidva.com>
+Licensed under the Apache License, Version 2.0 (the "License");
+you may not use this file except in compliance with the License.
+You may obtain a copy of the License at
+
+http://www.apache.org/licenses/LICENSE-2.0
+
+Unless required by applicable law or agreed to in writing, software
+distributed under the License is distributed on an "AS IS" BASIS,
+WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+See the License for the specific language governing permissions and
+limitations under the License.
+*/
+use crate::activity::*;
+use crate::fmt_age;
+use crate::gpdbcli::{desc_segment, utility_option, Capabilities, Segment};
+use crate::graph::WFGraph;
+use crate::lock::*;
+use std::collections::{BTreeMap, HashMap};
+
+// The locks an orphan holds by one backend on one segment.
+pub struct OrphanBackend {
+    pub segid: i64,
+    // None if pg_locks does not tell.
+    pub pid: Option<i64>,
+    // what pg_stat_activity of the segment says about the backend, None if it is not there (any
+    // more).
+    pub activity: Option<Activity>,
+    pub locks: Vec<String>,
+}
+
+// A session holding locks without a live backend on the coordinator, which happens when the QD
+// goes away and leaves QEs behind. Nothing will ever release these locks but
+// pg_terminate_backend() on the segments.
+pub struct Orphan {
+    pub sessid: SessionId,
+    pub backends: Vec<OrphanBackend>,
+    // sessions waiting for the orphan.
+    pub blocking: Vec<SessionId>,
+}
+
+pub struct Orphans {
+    pub orphans: Vec<Orphan>,
+    segments: HashMap<i64, Segment>,
+    // PGOPTIONS of the printed pg_terminate_backend() commands.
+    utility: &'static str,
+}
+
+impl Orphans {
+    // `activity` is pg_stat_activity of the coordinator, `backends` the ones of the segments, see
+    // ActivityInfo::get_segments(). Background workers and prepared transactions have no session
+    // and are never orphans.
+    pub fn new(
+        wfg: &WFGraph,
+        activity: &ActivityInfo,
+        backends: &SegmentBackends,
+        caps: &Capabilities,
+    ) -> Orphans {
+        let info = wfg.lockinfo();
+        // session -> (segment, pid) -> locks.
+        let mut held: BTreeMap<SessionId, BTreeMap<_, Vec<String>>> = BTreeMap::new();
+        for (sessid, lock) in info.all_granted() {
+            if sessid <= 0 || activity.sessions.contains_key(&sessid) {
+                continue;
+            }
+            let segid = info.get_obj(lock.objid).segment().unwrap_or(-1);
+            held.entry(sessid)
+                .or_default()
+                .entry((segid, lock.pid))
+                .or_default()
+                .push(format!("{} on {}", lock.mode, info.desc_obj(lock.objid)));
+        }
+
+        let mut orphans = vec![];
+        for (sessid, locks) in held {
+            let backends = locks
+                .into_iter()
+                .map(|((segid, pid), mut locks)| {
+                    locks.sort_unstable();
+                    OrphanBackend {
+                        segid,
+                        pid,
+                        activity: pid
+                            .and_then(|pid| backends.get(&(segid, pid)))
+                            .map(|(_, activity)| activity.clone()),
+                        locks,
+                    }
+                })
+                .collect();
+            orphans.push(Orphan {
+                sessid,
+                backends,
+                blocking: wfg.blocking(sessid),
+            });
+        }
+
+        Orphans {
+            orphans,
+            segments: info.segments.clone(),
+            utility: utility_option(caps),
+        }
+    }
+
+    fn desc_backend(&self, backend: &OrphanBackend) -> String {
+        let pid = match backend.pid {
+            Some(pid) => format!("pid {}", pid),
+            None => String::from("unknown pid"),
+        };
+        let state = match &backend.activity {
+            Some(activity) => format!(
+                "{}, in transaction for {}",
+                activity.state.as_deref().unwrap_or("?"),
+                fmt_age(activity.xact_age)
+            ),
+            None => String::from("not in pg_stat_activity"),
+        };
+        format!(
+            "{} {}({})",
+            desc_segment(backend.segid, self.segments.get(&backend.segid)),
+            pid,
+            state
+        )
+    }
+
+    // What to run to release the locks, the coordinator cannot signal the backends of a segment.
+    fn desc_terminate(&self, backend: &OrphanBackend) -> Option<String> {
+        let pid = backend.pid?;
+        let query = format!("select pg_terminate_backend({})", pid);
+        Some(match self.segments.get(&backend.segid) {
+            Some(segment) => format!(
+                "PGOPTIONS='{}' psql -h {} -p {} -d postgres -c '{}'",
+                self.utility, segment.hostname, segment.port, query
+            ),
+            None => format!("on segment {} in utility mode: {};", backend.segid, query),
+        })
+    }
+
+    pub fn render(&self) -> String {
+        let mut out = vec![format!("orphan sessions: {}", self.orphans.len())];
+        let mut commands = vec![];
+        for orphan in self.orphans.iter() {
+            out.push(format!(
+                "session {} holds locks but has no backend on the coordinator{}",
+                orphan.sessid,
+                if orphan.blocking.is_empty() {
+                    String::new()
+                } else {
+                    format!(
+                        ", blocking sessions {}",
+                        orphan
+                            .blocking
+                            .iter()
+                            .map(SessionId::to_string)
+                            .collect::<Vec<_>>()
+                            .join(", ")
+                    )
+                }
+            ));
+            for backend in orphan.backends.iter() {
+                out.push(format!("  {}", self.desc_backend(backend)));
+                for lock in backend.locks.iter() {
+                    out.push(format!("    {}", lock));
+                }
+                commands.extend(self.desc_terminate(backend));
+            }
+        }
+        if !commands.is_empty() {
+            out.push(String::from(
+                "to release their locks, after checking again that they are still there:",
+            ));
+            out.extend(commands);
+        }
+        out.join("\n")
+    }
+}
@@ -0,0 +1,150 @@
+/*
+Copyright 2020 <盏一 w@hidva.com>
+Licensed under the Apache License, Version 2.0 (the "License");
+you may not use this file except in compliance with the License.
+You may obtain a copy of the License at
+
+http://www.apache.org/licenses/LICENSE-2.0
+
+Unless required by applicable law or agreed to in writing, software
+distributed under the License is distributed on an "AS IS" BASIS,
+WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+See the License for the specific language governing permissions and
+limitations under the License.
+*/
+use crate::activity::*;
+use crate::graph::dot;
+use crate::lock::*;
+use crate::{fmt_age, get_or_default};
+use std::collections::{HashMap, HashSet};
+
+// Relation lock modes taken by statements writing to the relation. Greenplum takes ExclusiveLock
+// for UPDATE and DELETE when the global deadlock detector is off.
+const WRITE_MODES: &[LockMode] = &[
+    LockMode::RowExclusiveLock,
+    LockMode::ExclusiveLock,
+    LockMode::AccessExclusiveLock,
+];
+
+// rw-dependencies between serializable transactions: reader -> writer if the reader holds a
+// SIReadLock on (a page or a tuple of) a relation the writer is writing to. This is coarser than
+// what PostgreSQL tracks itself, so an edge is a possible rw-conflict rather than a certain one.
+// Predicate locks never block, these edges are not waits, but a session with both incoming and
+// outgoing edges is the pivot of a dangerous structure, one of them may fail to serialize.
+#[derive(Default)]
+pub struct RWGraph {
+    edges: HashMap<SessionId, Vec<SessionId>>,
+    // (reader, writer) -> relations.
+    relations: HashMap<(SessionId, SessionId), Vec<String>>,
+    activity: ActivityInfo,
+}
+
+impl RWGraph {
+    pub fn new(info: &LockInfo, activity: ActivityInfo) -> RWGraph {
+        let mut readers: HashMap<(i64, i64), HashSet<SessionId>> = HashMap::new();
+        let mut writers: HashMap<(i64, i64), HashSet<SessionId>> = HashMap::new();
+        let mut relnames: HashMap<(i64, i64), String> = HashMap::new();
+        for (sessid, lock) in info.all_granted() {
+            let obj = info.get_obj(lock.objid);
+            let key = match obj.relation_key() {
+                Some(key) => key,
+                None => continue,
+            };
+            if lock.mode == LockMode::SIReadLock {
+                get_or_default(&mut readers, key).insert(sessid);
+            } else if WRITE_MODES.contains(&lock.mode) {
+                get_or_default(&mut writers, key).insert(sessid);
+            } else {
+                continue;
+            }
+            relnames.entry(key).or_insert_with(|| {
+                info.get_relname(lock.objid)
+                    .map(String::from)
+                    .unwrap_or_else(|| format!("{}.{}", key.0, key.1))
+            });
+        }
+
+        let mut ret = RWGraph {
+            activity,
+            ..RWGraph::default()
+        };
+        for (key, sessions) in readers.iter() {
+            let writing = match writers.get(key) {
+                Some(writing) => writing,
+                None => continue,
+            };
+            for &reader in sessions.iter() {
+                for &writer in writing.iter().filter(|&&w| w != reader) {
+                    let relations = get_or_default(&mut ret.relations, (reader, writer));
+                    if relations.is_empty() {
+                        get_or_default(&mut ret.edges, reader).push(writer);
+                    }
+                    relations.push(relnames[key].clone());
+                }
+            }
+        }
+        for writers in ret.edges.values_mut() {
+            writers.sort_unstable();
+        }
+        ret
+    }
+
+    fn sessions(&self) -> Vec<SessionId> {
+        let mut sessions: Vec<SessionId> = self
+            .edges
+            .iter()
+            .flat_map(|(&reader, writers)| writers.iter().copied().chain(Some(reader)))
+            .collect();
+        sessions.sort_unstable();
+        sessions.dedup();
+        sessions
+    }
+
+    // Sessions both reading what another one writes and writing what another one reads.
+    pub fn pivots(&self) -> Vec<SessionId> {
+        let writers: HashSet<SessionId> = self.edges.values().flatten().copied().collect();
+        let mut pivots: Vec<SessionId> = self
+            .edges
+            .keys()
+            .copied()
+            .filter(|s| writers.contains(s))
+            .collect();
+        pivots.sort_unstable();
+        pivots
+    }
+
+    fn desc_session(&self, sessid: SessionId) -> String {
+        match self.activity.sessions.get(&sessid) {
+            Some(activity) => format!(
+                "session {}({}, in transaction for {})",
+                sessid,
+                activity.usename.as_deref().unwrap_or("?"),
+                fmt_age(activity.xact_age)
+            ),
+            None => format!("session {}", sessid),
+        }
+    }
+
+    pub fn render(&self) -> String {
+        let mut out = vec![dot::render_tiny(&self.edges, self.sessions().into_iter())];
+        out.push(String::from("/*rw-dependencies, reader -> writer"));
+        let mut pairs: Vec<&(SessionId, SessionId)> = self.relations.keys().collect();
+        pairs.sort_unstable();
+        for &(reader, writer) in pairs {
+            out.push(format!(
+                "{} read {} written by {};",
+                self.desc_session(reader),
+                self.relations[&(reader, writer)].join(", "),
+                self.desc_session(writer)
+            ));
+        }
+        for pivot in self.pivots() {
+            out.push(format!(
+                "dangerous structure: {} is a pivot, it may fail to serialize;",
+                self.desc_session(pivot)
+            ));
+        }
+        out.push(String::from("*/"));
+        out.join("\n")
+    }
+}